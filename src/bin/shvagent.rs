@@ -3,43 +3,39 @@ use std::{env};
 use std::time::Duration;
 use chainpack::{RpcMessage, RpcMessageMetaTags, RpcValue, metamethod};
 
-use chainpack::rpcmessage::{RpcError, RpcErrorCode};
 use chainpack::rpcvalue::List;
 use chainpack::metamethod::{MetaMethod};
 
-use shvapp::{Connection, DEFAULT_PORT, shvjournal};
-use shvapp::client::{ConnectionParams};
-use shvapp::shvtree::{ShvTree, ShvNode, ProcessRequestResult, RpcResponseSender};
+use shvapp::{shvjournal, Error};
+use shvapp::client::{protocol_from_str, ConnectionParams, ConnectionEvent, ReconnectingClient};
+use shvapp::shvtree::{ShvTree, AsyncShvNode, AccessLevel, RequestFuture, ShvNodeHelper, DEFAULT_METHOD_TIMEOUT};
 use shvapp::shvfsnode::FSDirNode;
+use shvapp::shvconnectionnode::ConnectionNode;
 
-use log::{warn, info, debug};
+use log::{warn};
 
 use async_std::{
     process::Command,
     // channel::{Receiver, Sender},
     // io::{stdin, BufReader, BufWriter},
-    net::{TcpStream},
     // prelude::*,
     task,
     // future,
 };
-use futures::FutureExt;
 use shvlog::LogConfig;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "shvagent", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "SHV Agent")]
 struct Cli {
-    #[structopt(name = "hostname", short = "-s", long = "--host", default_value = "127.0.0.1")]
-    host: String,
-    #[structopt(short = "-p", long = "--port", default_value = DEFAULT_PORT)]
-    port: u16,
-    #[structopt(short = "-u", long = "--user")]
-    user: String,
-    #[structopt(long = "--password")]
-    password: String,
-    #[structopt(long = "--device-id")]
+    #[structopt(short = "-s", long = "--url", default_value = "tcp://127.0.0.1:3755", help = "Broker URL, for example: tcp://user@host:port?password=secret&devid=dev1, login types: sha1 (default), plain, token, schemes: tcp, ssl, ws, wss, unix:/path/to/socket, serial:/dev/ttyXXX")]
+    url: String,
+    #[structopt(long = "--device-id", help = "Device ID, overrides devid URL parameter")]
     device_id: Option<String>,
-    #[structopt(short = "-m", long = "--mount-point")]
+    #[structopt(long = "--password-file", help = "Read password or token from file, overrides password URL parameter")]
+    password_file: Option<String>,
+    #[structopt(long = "--password-env", help = "Read password or token from environment variable, overrides password URL parameter")]
+    password_env: Option<String>,
+    #[structopt(short = "-m", long = "--mount-point", help = "Mount point, overrides mount URL parameter")]
     mount_point: Option<String>,
     #[structopt(short, long, help = "SHV journal directory, /tmp/shvjournal/shvagent if not specified")]
     journal_dir: Option<String>,
@@ -51,11 +47,18 @@ struct Cli {
     verbosity: Vec<String>,
     #[structopt(short, long, help = "Log levels for modules, for example: client:W or :T, default is :W if not specified")]
     debug: Vec<String>,
+    #[structopt(long, help = "Protocol of sent frames: chainpack or cpon, overrides protocol URL parameter")]
+    protocol: Option<String>,
+    #[structopt(long = "--reply-in-request-protocol", help = "Send responses in protocol of the request")]
+    reply_in_request_protocol: bool,
+    #[structopt(long = "--record", help = "Record all the broker traffic to file, CPON if the file has .cpon extension, ChainPack otherwise")]
+    record_file: Option<String>,
     #[structopt(short = "-e", long = "--export-dir", help = "Directory, which will be exported as 'fs' subnode")]
     export_dir: Option<String>,
 }
 
 // const DEFAULT_RPC_TIMEOUT_MSEC: u64 = 5000;
+const RUN_CMD_TIMEOUT: Duration = Duration::from_secs(60);
 pub(crate) fn main() -> shvapp::Result<()> {
     task::block_on(try_main())
 }
@@ -66,7 +69,7 @@ async fn try_main() -> shvapp::Result<()> {
 
     let log_config = LogConfig::new(&cli.debug, &cli.verbosity);
     let verbosity_string = log_config.verbosity_string();
-    let _log_handle = shvlog::init(log_config)?;
+    let _log_handle = shvlog::init(log_config).map_err(|e| Error::Other(format!("Logger init error: {}", e)))?;
     /*
     if let Some(journal_dir) = cli.journal_dir {
         let options = shvjournal::Options {
@@ -91,18 +94,33 @@ async fn try_main() -> shvapp::Result<()> {
     //log::debug!("debug");
     //log::trace!("trace");
 
-    let device_id = cli.device_id.unwrap_or("".into());
-    // Get the remote address to connect to
-    // let rpc_timeout = Duration::from_millis(DEFAULT_RPC_TIMEOUT_MSEC);
-    let mut connection_params = ConnectionParams::new(&cli.host, cli.port, &cli.user, &cli.password);
-    connection_params.device_id = device_id.to_string();
-    connection_params.mount_point = cli.mount_point.unwrap_or("".to_string());
+    let mut connection_params = ConnectionParams::from_url(&cli.url)?;
+    if let Some(device_id) = cli.device_id {
+        connection_params.device_id = device_id;
+    }
+    if cli.password_file.is_some() {
+        connection_params.password_file = cli.password_file;
+    }
+    if cli.password_env.is_some() {
+        connection_params.password_env = cli.password_env;
+    }
+    if let Some(mount_point) = cli.mount_point {
+        connection_params.mount_point = mount_point;
+    }
+    if let Some(protocol) = cli.protocol {
+        connection_params.protocol = protocol_from_str(&protocol)?;
+    }
+    if cli.reply_in_request_protocol {
+        connection_params.reply_in_request_protocol = true;
+    }
+    connection_params.record_file = cli.record_file;
+    log::info!("Broker URL: {}", connection_params.to_url());
+    let device_id = connection_params.device_id.clone();
 
     let mut shv_tree = ShvTree::new();
-    shv_tree.add_node("", Box::new(DeviceNode {
+    shv_tree.add_async_node("", Box::new(DeviceNode {
             app_name: "ShvAgent".into(),
             device_id,
-            rpc_sender: shv_tree.response_sender.clone(),
         }));
     //let exported_dir = dirs::home_dir();
     if let Some(export_dir) = cli.export_dir {
@@ -110,171 +128,100 @@ async fn try_main() -> shvapp::Result<()> {
                 root: export_dir.into(),
             }));
     }
-    loop {
-        // Establish a connection
-        let addr = format!("{}:{}", connection_params.host, connection_params.port);
-        info!("connecting to: {}", addr);
-        let stream = TcpStream::connect(&addr).await?;
-        info!("connected to: {}", addr);
-        let (mut connection, mut client) = Connection::new(stream, connection_params.protocol);
-        task::spawn(async move {
-            info!("Spawning connection message loop");
-            match connection.exec().await {
-                Ok(_) => {
-                    info!("Connection message loop finished Ok");
-                }
-                Err(e) => {
-                    warn!("Connection message loop finished with error: {}", e);
-                }
-            }
-        });
-        match client.login(&connection_params).await {
-            Ok(_) => {
-                if let Some(heartbeat_interval) = connection_params.heartbeat_interval {
-                    client.spawn_ping_task(heartbeat_interval);
-                }
-                //let fut_client = client.receive_message();
-                //let fut_nodes = shv_tree.response_receiver.recv();
-                loop {
-                    futures::select! {
-                        msg = client.receive_message().fuse() => {
-                            match msg {
-                                Ok(msg) => {
-                                    //info!(target: "rpcmsg", "<== Message arrived: {}", msg);
-                                    if msg.is_request() {
-                                        let ret_val = shv_tree.process_request(&msg);
-                                        if let Ok(None) = ret_val {
-                                            // ret val will be sent async in handler
-                                        }
-                                        else {
-                                            match msg.prepare_response() {
-                                                Ok(mut resp_msg) => {
-                                                    match ret_val {
-                                                        Ok(None) => {}
-                                                        Ok(Some(rv)) => {
-                                                            resp_msg.set_result(rv);
-                                                            debug!(target: "rpcmsg", "==> Sending response: {}", &resp_msg);
-                                                            client.send_message(&resp_msg).await?;
-                                                        }
-                                                        Err(e) => {
-                                                            resp_msg.set_error(RpcError::new(RpcErrorCode::MethodCallException, &e.to_string()));
-                                                            debug!(target: "rpcmsg", "==> Sending error: {}", &resp_msg);
-                                                            client.send_message(&resp_msg).await?;
-                                                        }
-                                                    }
-                                                }
-                                                Err(e) => {
-                                                    warn!("Create response meta error: {}.", e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    warn!("Read client message error: {}.", e);
-                                    break;
-                                }
-                            }
-                        },
-                        msg = shv_tree.response_receiver.recv().fuse() => {
-                            match msg {
-                                Ok(msg) => {
-                                    debug!(target: "rpcmsg", "<== NodesTree message arrived: {}", msg);
-                                    client.send_message(&msg).await?;
-                                }
-                                Err(e) => {
-                                    warn!("Read node message error: {}.", e);
-                                    break;
-                                }
-                            }
-                        },
-                    }
-
-                }
-            }
-            Err(e) => {
-                warn!("Login error: {}", e);
-                task::sleep(Duration::from_secs(5)).await;
-            }
+    let reconnecting_client = ReconnectingClient::new(&connection_params);
+    shv_tree.add_node(".app/connection", Box::new(ConnectionNode::new(reconnecting_client.stats())));
+    let connection_events = reconnecting_client.spawn();
+    while let Ok(event) = connection_events.recv().await {
+        let client = match event {
+            ConnectionEvent::LoggedIn(client) => client,
+            _ => continue,
         };
+        if let Err(e) = shv_tree.serve(&client).await {
+            warn!("Serve requests error: {}.", e);
+        }
     }
+    Ok(())
 }
 
 struct DeviceNode {
     app_name: String,
     device_id: String,
-    rpc_sender: RpcResponseSender,
 }
 
-impl ShvNode for DeviceNode {
-    fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> ProcessRequestResult {
-        let method = request.method().ok_or("Empty method")?;
+impl DeviceNode {
+    fn methods() -> Vec<MetaMethod> {
+        vec![
+            MetaMethod { name: "dir".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::None.into(), access_grant: RpcValue::from("bws"), description: "".into() },
+            // answered by the tree, which knows the mounted child nodes
+            MetaMethod { name: "ls".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::None.into(), access_grant: RpcValue::from("bws"), description: "".into() },
+            MetaMethod { name: "appName".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::IsGetter.into(), access_grant: RpcValue::from("bws"), description: "".into() },
+            MetaMethod { name: "deviceId".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::IsGetter.into(), access_grant: RpcValue::from("rd"), description: "".into() },
+            MetaMethod { name: "runCmd".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::None.into(), access_grant: RpcValue::from("cmd"), description: "".into() },
+        ]
+    }
+}
+
+impl AsyncShvNode for DeviceNode {
+    fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> RequestFuture {
+        let method = request.method().unwrap_or_default();
         if shv_path.is_empty() {
             if method == "dir" {
-                let methods = [
-                    MetaMethod { name: "dir".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::None.into(), access_grant: RpcValue::from("bws"), description: "".into() },
-                    //MetaMethod { name: "ls".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::None.into(), access_grant: RpcValue::from("bws"), description: "".into() },
-                    MetaMethod { name: "appName".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::IsGetter.into(), access_grant: RpcValue::from("bws"), description: "".into() },
-                    MetaMethod { name: "deviceId".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::IsGetter.into(), access_grant: RpcValue::from("rd"), description: "".into() },
-                    MetaMethod { name: "runCmd".into(), signature: metamethod::Signature::RetParam, flags: metamethod::Flag::None.into(), access_grant: RpcValue::from("wr"), description: "".into() },
-                ];
                 let mut lst = List::new();
-                for mm in methods.iter() {
+                for mm in DeviceNode::methods().iter() {
                     lst.push(mm.to_rpcvalue(255));
                 }
-                return Ok(Some(lst.into()));
+                return Box::pin(async move { Ok(lst.into()) });
             }
             if method == "appName" {
-                return Ok(Some(RpcValue::from(&self.app_name)));
+                let app_name = RpcValue::from(&self.app_name);
+                return Box::pin(async move { Ok(app_name) });
             }
             if method == "deviceId" {
-                return Ok(Some(RpcValue::from(&self.device_id)));
+                let device_id = RpcValue::from(&self.device_id);
+                return Box::pin(async move { Ok(device_id) });
             }
             if method == "runCmd" {
-                let request = request.clone();
-                // let shv_path = shv_path.to_string();
-                let client_sender = self.rpc_sender.clone();
-                task::spawn(async move {
-                    async fn run_cmd(request: &RpcMessage) -> shvapp::Result<RpcValue> {
-                        let params = request.params().ok_or("No params")?;
-                        let cmd = if params.is_list() {
-                            let params = params.as_list();
-                            if params.is_empty() {
-                                return Err("Param list is empty".into());
-                            }
-                            params[0].as_str()
-                        }
-                        else if params.is_string() {
-                            params.as_str()
+                let params = request.params().cloned();
+                return Box::pin(async move {
+                    let params = params.ok_or_else(|| Error::InvalidParams("No params".into()))?;
+                    let cmd = if params.is_list() {
+                        let params = params.as_list();
+                        if params.is_empty() {
+                            return Err(Error::InvalidParams("Param list is empty".into()));
                         }
-                        else {
-                            return Err("Invalid params".into());
-                        };
-                        let output = Command::new(cmd)
-                            //.args(args)
-                            .output().await?;
-                        let out: &[u8] = &output.stdout;
-                        return Ok(RpcValue::from(out))
+                        params[0].as_str().to_string()
                     }
-                    match request.prepare_response() {
-                        Ok(mut resp_msg) => {
-                            match run_cmd(&request).await {
-                                Ok(rv) => { resp_msg.set_result(rv); }
-                                Err(e) => { resp_msg.set_error(RpcError::new(RpcErrorCode::MethodCallException, &e.to_string())); }
-                            }
-                            match client_sender.send(resp_msg).await {
-                                Ok(_) => {}
-                                Err(e) => { warn!("Send response error: {}.", e); }
-                            }
-                        }
-                        Err(e) => {
-                            warn!("Create response error: {}.", e);
-                        }
+                    else if params.is_string() {
+                        params.as_str().to_string()
                     }
+                    else {
+                        return Err(Error::InvalidParams("Invalid params".into()));
+                    };
+                    // the command is killed, when the handler is dropped on timeout
+                    let output = Command::new(cmd)
+                        //.args(args)
+                        .kill_on_drop(true)
+                        .output().await?;
+                    let out: &[u8] = &output.stdout;
+                    Ok(RpcValue::from(out))
                 });
-                return Ok(None);
             }
         }
-        Err(format!("Unknown method '{}' on path '{}'", method, shv_path).into())
+        let err = Error::MethodNotFound(format!("Unknown method '{}' on path '{}'", method, shv_path));
+        Box::pin(async move { Err(err) })
+    }
+    fn required_access(&self, shv_path: &str, method: &str) -> AccessLevel {
+        if shv_path.is_empty() {
+            if let Some(access) = ShvNodeHelper::method_access(DeviceNode::methods().iter(), method) {
+                return access;
+            }
+        }
+        AccessLevel::default_for_method(method)
+    }
+    fn method_timeout(&self, method: &str) -> Option<Duration> {
+        match method {
+            "runCmd" => Some(RUN_CMD_TIMEOUT),
+            _ => Some(DEFAULT_METHOD_TIMEOUT),
+        }
     }
 }
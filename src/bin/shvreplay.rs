@@ -0,0 +1,57 @@
+use structopt::StructOpt;
+use std::path::Path;
+use chainpack::RpcMessageMetaTags;
+use chainpack::rpcframe::Protocol;
+use async_std::task;
+
+use shvapp::{Connection, Error};
+use shvapp::framerecorder::{read_recording, FrameDirection, RecordFormat, ReplayStream};
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "shvreplay", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "Print or replay SHV traffic recorded by shvagent --record")]
+struct Cli {
+    #[structopt(help = "Recording file")]
+    file: String,
+    #[structopt(long, help = "Recording format: chainpack or cpon, guessed from file extension if not specified")]
+    format: Option<String>,
+    #[structopt(long, help = "Print only frames of direction: send or receive")]
+    direction: Option<String>,
+    #[structopt(short, long, help = "Feed received frames to connection and print messages delivered to the client")]
+    replay: bool,
+}
+
+pub(crate) fn main() -> shvapp::Result<()> {
+    let cli = Cli::from_args();
+    let format = match cli.format.as_deref() {
+        None => RecordFormat::from_path(Path::new(&cli.file)),
+        Some("chainpack") => RecordFormat::ChainPack,
+        Some("cpon") => RecordFormat::Cpon,
+        Some(format) => return Err(Error::Other(format!("Invalid format: '{}'", format))),
+    };
+    let direction = cli.direction.as_deref().map(FrameDirection::from_str).transpose()?;
+    let frames = read_recording(&cli.file, format)?;
+    if cli.replay {
+        return task::block_on(async {
+            let (mut connection, client) = Connection::new(ReplayStream::new(&frames)?, Protocol::ChainPack);
+            let connection_task = task::spawn(async move { connection.exec().await });
+            while let Ok(msg) = client.receive_message().await {
+                println!("{}", msg);
+            }
+            match connection_task.await {
+                Ok(_) | Err(Error::ConnectionClosed) => Ok(()),
+                Err(e) => Err(e),
+            }
+        });
+    }
+    for recorded in frames.iter().filter(|recorded| direction.map_or(true, |direction| recorded.direction == direction)) {
+        let prompt = match recorded.direction { FrameDirection::Send => "<===", FrameDirection::Receive => "===>" };
+        match recorded.frame.to_rpcmesage() {
+            Ok(msg) => {
+                let rq_id = msg.request_id().map(|id| id.to_string()).unwrap_or_default();
+                println!("{} {} {:>6} {}", recorded.timestamp.to_iso_string(), prompt, rq_id, msg);
+            }
+            Err(_) => println!("{} {} {}", recorded.timestamp.to_iso_string(), prompt, recorded.frame),
+        }
+    }
+    Ok(())
+}
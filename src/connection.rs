@@ -1,86 +1,472 @@
 use chainpack::rpcframe::{RpcFrame, Protocol};
-use crate::client::{Client};
+use crate::Error;
+use crate::client::{Client, DEFAULT_RPC_CALL_TIMEOUT};
+use crate::throttle::{ClientLimits, Throttle};
+use crate::framerecorder::{FrameDirection, FrameObserver};
 use bytes::{Buf, BytesMut};
-use chainpack::{ChainPackWriter, Writer, CponWriter};
-use log::{debug, error};
+use chainpack::{ChainPackWriter, Writer, CponWriter, RpcMessageMetaTags};
+use log::{debug, warn, error};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+use chainpack::{DateTime, RpcValue};
+use chainpack::rpcmessage::{RpcError, RpcErrorCode};
 use async_std::{
-    channel::{Receiver},
+    channel::{Receiver, Sender, TrySendError},
     // io::{stdin, BufReader, BufWriter},
-    net::{TcpStream},
     prelude::*,
     // task,
 };
-use futures::{select, FutureExt};
-use async_broadcast::{broadcast};
+use futures::{select_biased, FutureExt, AsyncRead, AsyncWrite};
 
-enum LogFramePrompt {
-    Send,
-    Receive,
+pub type RqId = i64;
+pub type SubscriptionId = u64;
+
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Reasons, why the received byte stream cannot be parsed to frames.
+#[derive(Debug)]
+pub enum FrameError {
+    /// Declared or received frame size exceeds the limit
+    TooLarge { size: usize, max_size: usize },
+    /// Frame header or content cannot be parsed
+    Malformed(String),
+    /// Stream ended in the middle of a frame
+    Truncated { received: usize },
+}
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooLarge { size, max_size } => write!(f, "Frame too large, size: {} max size: {}", size, max_size),
+            FrameError::Malformed(msg) => write!(f, "Malformed frame: {}", msg),
+            FrameError::Truncated { received } => write!(f, "Stream closed in the middle of frame, {} bytes received", received),
+        }
+    }
+}
+impl std::error::Error for FrameError {}
+
+/// Traffic counters, shared by connection, its clients and the `.app/connection` node.
+///
+/// Counters are not reset on reconnect, if the same instance is passed to the new connection.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+    pub frames_sent: AtomicU64,
+    pub frames_received: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    pub bytes_received: AtomicU64,
+    pub parse_errors: AtomicU64,
+    /// Successful logins after the connection was lost
+    pub reconnects: AtomicU64,
+    /// RPC calls waiting for response
+    pub pending_calls: AtomicU64,
+    /// RPC calls delayed because of `ClientLimits::max_pending_calls`
+    pub throttled_calls: AtomicU64,
+    /// Messages delayed because of `ClientLimits::max_messages_per_sec`
+    pub rate_limited: AtomicU64,
+    /// Signals dropped because the subscriber did not read them
+    pub dropped_signals: AtomicU64,
+    // msec since epoch, 0 if never
+    last_send_msec: AtomicI64,
+    last_receive_msec: AtomicI64,
+    // heartbeat round trip time in usec, 0 if not measured yet
+    rtt_usec: AtomicU64,
+}
+impl ConnectionStats {
+    fn record_sent(&self, bytes: usize) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_send_msec.store(DateTime::now().epoch_msec(), Ordering::Relaxed);
+    }
+    fn record_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_receive_msec.store(DateTime::now().epoch_msec(), Ordering::Relaxed);
+    }
+    fn record_frame_received(&self) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+    }
+    fn record_parse_error(&self) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_call_started(&self) {
+        self.pending_calls.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_call_finished(&self) {
+        self.pending_calls.fetch_sub(1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_throttled_call(&self) {
+        self.throttled_calls.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+    fn record_dropped_signal(&self) {
+        self.dropped_signals.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn set_rtt(&self, rtt: Duration) {
+        self.rtt_usec.store(rtt.as_micros() as u64, Ordering::Relaxed);
+    }
+    pub fn rtt(&self) -> Option<Duration> {
+        match self.rtt_usec.load(Ordering::Relaxed) {
+            0 => None,
+            usec => Some(Duration::from_micros(usec)),
+        }
+    }
+    fn datetime(msec: i64) -> Option<DateTime> {
+        if msec == 0 { None } else { Some(DateTime::from_epoch_msec(msec)) }
+    }
+    pub fn last_send_time(&self) -> Option<DateTime> {
+        ConnectionStats::datetime(self.last_send_msec.load(Ordering::Relaxed))
+    }
+    pub fn last_receive_time(&self) -> Option<DateTime> {
+        ConnectionStats::datetime(self.last_receive_msec.load(Ordering::Relaxed))
+    }
+    fn elapsed(msec: i64) -> Option<Duration> {
+        match msec {
+            0 => None,
+            msec => Some(Duration::from_millis((DateTime::now().epoch_msec() - msec).max(0) as u64)),
+        }
+    }
+    /// Time elapsed since data was sent, None if nothing was sent yet
+    pub fn since_last_send(&self) -> Option<Duration> {
+        ConnectionStats::elapsed(self.last_send_msec.load(Ordering::Relaxed))
+    }
+    /// Time elapsed since data was received, None if nothing was received yet
+    pub fn since_last_receive(&self) -> Option<Duration> {
+        ConnectionStats::elapsed(self.last_receive_msec.load(Ordering::Relaxed))
+    }
+    /// Counter names as used in the `.app/connection` node
+    pub const KEYS: [&'static str; 13] = [
+        "framesSent", "framesReceived", "bytesSent", "bytesReceived", "parseErrors", "reconnects",
+        "lastSendTime", "lastReceiveTime", "rttMs", "pendingCalls", "throttledCalls", "rateLimited",
+        "droppedSignals",
+    ];
+    pub fn value(&self, key: &str) -> Option<RpcValue> {
+        let load = |cnt: &AtomicU64| RpcValue::from(cnt.load(Ordering::Relaxed));
+        let rv = match key {
+            "framesSent" => load(&self.frames_sent),
+            "framesReceived" => load(&self.frames_received),
+            "bytesSent" => load(&self.bytes_sent),
+            "bytesReceived" => load(&self.bytes_received),
+            "parseErrors" => load(&self.parse_errors),
+            "reconnects" => load(&self.reconnects),
+            "lastSendTime" => self.last_send_time().map(RpcValue::from).unwrap_or_else(|| RpcValue::from(())),
+            "lastReceiveTime" => self.last_receive_time().map(RpcValue::from).unwrap_or_else(|| RpcValue::from(())),
+            "rttMs" => self.rtt().map(|rtt| RpcValue::from(rtt.as_secs_f64() * 1000.)).unwrap_or_else(|| RpcValue::from(())),
+            "pendingCalls" => load(&self.pending_calls),
+            "throttledCalls" => load(&self.throttled_calls),
+            "rateLimited" => load(&self.rate_limited),
+            "droppedSignals" => load(&self.dropped_signals),
+            _ => return None,
+        };
+        Some(rv)
+    }
+    pub fn to_rpcvalue(&self) -> RpcValue {
+        let mut map = chainpack::rpcvalue::Map::new();
+        for key in ConnectionStats::KEYS.iter() {
+            if let Some(rv) = self.value(key) {
+                map.insert(key.to_string(), rv);
+            }
+        }
+        RpcValue::from(map)
+    }
+}
+
+/// Decode ChainPack unsigned int used as frame length prefix.
+///
+/// Returns value and number of header bytes, None if more data is needed.
+pub(crate) fn read_uint_data(buf: &[u8]) -> Option<(u64, usize)> {
+    let head = *buf.first()?;
+    let (mut num, len) = if head & 0x80 == 0 {
+        ((head & 0x7F) as u64, 1)
+    } else if head & 0x40 == 0 {
+        ((head & 0x3F) as u64, 2)
+    } else if head & 0x20 == 0 {
+        ((head & 0x1F) as u64, 3)
+    } else if head & 0x10 == 0 {
+        ((head & 0x0F) as u64, 4)
+    } else {
+        (0, (head & 0x0F) as usize + 4 + 1)
+    };
+    if buf.len() < len {
+        return None;
+    }
+    for b in &buf[1 .. len] {
+        num = (num << 8) | (*b as u64);
+    }
+    Some((num, len))
+}
+
+/// How frames are delimited in the byte stream.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Framing {
+    /// Frame is prefixed by its length (TCP, TLS, Unix socket)
+    Block,
+    /// SHV serial framing with STX/ETX and CRC, see `crate::serial`
+    Serial,
+}
+
+/// Byte stream the connection can run over, plain TCP or TLS for example.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncStream for T {}
+
+/// Commands sent from `Client` clones to the connection message loop.
+pub enum ConnectionCommand {
+    /// Send frame, no response is expected (response, signal, fire-and-forget request).
+    SendFrame(RpcFrame),
+    /// Send request frame and deliver the matching response to `response_sender`.
+    CallRpcMethod {
+        rq_id: RqId,
+        frame: RpcFrame,
+        response_sender: Sender<RpcFrame>,
+    },
+    /// Remove pending call, late response will be dropped.
+    AbortRpcCall(RqId),
+    /// Deliver signals matching `path` and `method` to `sender`, `reply` gets the subscription ID
+    /// and state of the broker subscription of the same signal.
+    Subscribe {
+        path: String,
+        method: String,
+        sender: Sender<RpcFrame>,
+        reply: Sender<(SubscriptionId, SubscribeStatus)>,
+    },
+    /// Result of broker subscription made by the first subscriber of signal,
+    /// all the local subscribers of the signal are removed on error.
+    BrokerSubscribed {
+        path: String,
+        method: String,
+        error: Option<RpcError>,
+    },
+    /// Remove subscriber, `reply` gets number of local subscribers of the same signal left,
+    /// `None` if there is no such subscriber.
+    Unsubscribe {
+        id: SubscriptionId,
+        reply: Sender<Option<usize>>,
+    },
+    /// Close connection after all the previously queued frames are sent,
+    /// `done` is dropped when the connection is closed.
+    Close { done: Sender<()> },
+}
+
+/// Broker subscription state reported to new subscriber
+pub enum SubscribeStatus {
+    /// First subscriber of the signal, it must subscribe on broker and send `ConnectionCommand::BrokerSubscribed`
+    First,
+    /// Broker subscription exists already
+    Active,
+    /// Broker subscription of the first subscriber is in progress, its error or `None` will be received
+    Pending(Receiver<Option<RpcError>>),
+}
+
+/// Broker subscription shared by local subscribers of the same path and method
+enum BrokerSubscription {
+    /// subscribe call made by subscriber `owner` is in progress, waiters get its result
+    Pending { owner: SubscriptionId, waiters: Vec<Sender<Option<RpcError>>> },
+    Active,
+}
+
+struct Subscriber {
+    path: String,
+    method: String,
+    sender: Sender<RpcFrame>,
+}
+impl Subscriber {
+    /// Signal matches when its path is the subscribed path or below it, empty method matches any signal.
+    fn matches(&self, path: &str, method: &str) -> bool {
+        let path_match = self.path.is_empty()
+            || (path.starts_with(&self.path) && (path.len() == self.path.len() || path[self.path.len() ..].starts_with('/')));
+        path_match && (self.method.is_empty() || self.method == method)
+    }
 }
 
 //#[derive(Debug)]
 pub struct Connection {
-    stream: TcpStream,
+    stream: Box<dyn AsyncStream>,
+    framing: Framing,
     // The buffer for reading frames.
     buffer: BytesMut,
-    from_client: Receiver<RpcFrame>,
-    // to_client: (Sender<Frame>, Receiver<Frame>),
-    // we cannot use async-std MPMC channel because only one receiver is notified here
-    // and we need to broadcast message to all the clients
-    // https://docs.rs/async-broadcast/latest/async_broadcast/#difference-with-async-channel
-    to_client: async_broadcast::Sender<RpcFrame>,
+    max_frame_size: usize,
+    from_client: Receiver<ConnectionCommand>,
+    // responses and heart-beat, served before the queued requests and signals
+    priority_from_client: Receiver<ConnectionCommand>,
+    // requests and signals, every frame is dispatched to exactly one receiver
+    to_client: Sender<RpcFrame>,
+    // responses are routed to the waiting caller only
+    pending_rpc_calls: BTreeMap<RqId, Sender<RpcFrame>>,
+    // signals matching a subscription are delivered to the subscribers only
+    subscribers: BTreeMap<SubscriptionId, Subscriber>,
+    // keyed by subscribed path and method
+    broker_subscriptions: BTreeMap<(String, String), BrokerSubscription>,
+    next_subscription_id: SubscriptionId,
+    stats: Arc<ConnectionStats>,
+    observers: Vec<Box<dyn FrameObserver>>,
+    // protocol of received requests waiting for response, None if responses are sent as they are
+    request_protocols: Option<RequestProtocols>,
+}
+
+/// Protocols of received requests by request ID, the oldest request is forgotten when full
+#[derive(Default)]
+struct RequestProtocols {
+    protocols: BTreeMap<RqId, Protocol>,
+    // request IDs in order of arrival
+    order: VecDeque<RqId>,
+}
+impl RequestProtocols {
+    // forget requests, which were never answered
+    const MAX_LEN: usize = 1024;
+
+    fn insert(&mut self, rq_id: RqId, protocol: Protocol) {
+        if self.protocols.insert(rq_id, protocol).is_some() {
+            self.order.retain(|id| *id != rq_id);
+        }
+        self.order.push_back(rq_id);
+        if self.order.len() > RequestProtocols::MAX_LEN {
+            if let Some(oldest) = self.order.pop_front() {
+                self.protocols.remove(&oldest);
+            }
+        }
+    }
+    fn remove(&mut self, rq_id: RqId) -> Option<Protocol> {
+        let protocol = self.protocols.remove(&rq_id)?;
+        // responses usually come in order of requests, so the ID is near the front
+        if let Some(pos) = self.order.iter().position(|id| *id == rq_id) {
+            self.order.remove(pos);
+        }
+        Some(protocol)
+    }
 }
 
 impl Connection {
-    pub fn new(stream: TcpStream, protocol: Protocol) -> (Connection, Client) {
-        // to_client_capacity 1 should be sufficient, the socket reader will be blocked
-        // if the channel will be full (in case of async_broadcast implementation).
-        // If some client will not read receive end, then whole app will be blocked !!!
-        // BUT the login function makes 2 RPC calls on self clones (because of RPC timeout, see Client::call_rpc_method),
-        // this is why capacity must be >= 2
-        const TO_CLIENT_CHANNEL_CAPACITY: usize = 2;
+    pub fn new<S: AsyncStream + 'static>(stream: S, protocol: Protocol) -> (Connection, Client) {
+        Connection::with_framing(stream, protocol, Framing::Block)
+    }
+    pub fn with_framing<S: AsyncStream + 'static>(stream: S, protocol: Protocol, framing: Framing) -> (Connection, Client) {
+        Connection::with_stats(stream, protocol, framing, Arc::new(ConnectionStats::default()))
+    }
+    /// Create connection counting traffic to `stats`
+    pub fn with_stats<S: AsyncStream + 'static>(stream: S, protocol: Protocol, framing: Framing, stats: Arc<ConnectionStats>) -> (Connection, Client) {
+        // Responses do not go through this channel, they are routed to the pending calls directly,
+        // so a client not reading requests and signals cannot cause lost RPC responses.
+        // The socket reader will be blocked if the channel is full.
+        const TO_CLIENT_CHANNEL_CAPACITY: usize = 256;
         const FROM_CLIENT_CHANNEL_CAPACITY: usize = 256;
         let (from_client_sender, from_client_receiver) = async_std::channel::bounded(FROM_CLIENT_CHANNEL_CAPACITY);
-        let (mut to_client_sender, to_client_receiver) = broadcast(TO_CLIENT_CHANNEL_CAPACITY);
-        to_client_sender.set_overflow(true);
+        let (priority_sender, priority_receiver) = async_std::channel::bounded(FROM_CLIENT_CHANNEL_CAPACITY);
+        let (to_client_sender, to_client_receiver) = async_std::channel::bounded(TO_CLIENT_CHANNEL_CAPACITY);
         (
             Connection {
-                stream,
+                stream: Box::new(stream),
+                framing,
                 buffer: BytesMut::with_capacity(4 * 1024),
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
                 from_client: from_client_receiver,
+                priority_from_client: priority_receiver,
                 to_client: to_client_sender,
-                // unfortunately, async-std channel is dispatch not broadcast
-                // we have to use broadcast channel from async-broadcast crate
+                pending_rpc_calls: BTreeMap::new(),
+                subscribers: BTreeMap::new(),
+                broker_subscriptions: BTreeMap::new(),
+                next_subscription_id: 1,
+                stats: stats.clone(),
+                observers: Vec::new(),
+                request_protocols: None,
             },
             Client {
                 sender: from_client_sender,
+                priority_sender,
                 receiver: to_client_receiver,
-                protocol
+                protocol,
+                throttle: Arc::new(Throttle::new(ClientLimits::default(), stats.clone())),
+                stats,
+                rpc_call_timeout: Some(DEFAULT_RPC_CALL_TIMEOUT),
             }
         )
     }
+    pub fn stats(&self) -> Arc<ConnectionStats> {
+        self.stats.clone()
+    }
+    /// Frames larger than `max_frame_size` are not accepted,
+    /// connection is dropped (block framing) or the frame is skipped (serial framing).
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+    /// Send response in protocol of its request instead of the client protocol.
+    ///
+    /// Peer talking CPON gets human readable responses, while the requests are sent in ChainPack for example.
+    pub fn set_reply_in_request_protocol(&mut self, enabled: bool) {
+        self.request_protocols = if enabled { Some(RequestProtocols::default()) } else { None };
+    }
+    /// Observer gets every frame sent or received by this connection, for example `FrameRecorder`.
+    pub fn add_frame_observer(&mut self, observer: Box<dyn FrameObserver>) {
+        self.observers.push(observer);
+    }
+    /// Run connection message loop.
+    ///
+    /// Returns `Ok` when closed by `Client::close()` or when all the clients are dropped.
+    /// All the pending RPC calls fail and client receivers get end of stream when `exec` returns.
     pub async fn exec(&mut self) -> crate::Result<()> {
+        let result = self.exec_loop().await;
+        self.shutdown();
+        result
+    }
+    fn shutdown(&mut self) {
+        if !self.pending_rpc_calls.is_empty() {
+            debug!("Connection closed, failing {} pending RPC calls", self.pending_rpc_calls.len());
+        }
+        // dropping senders wakes up waiting callers
+        self.pending_rpc_calls.clear();
+        self.subscribers.clear();
+        self.broker_subscriptions.clear();
+        self.to_client.close();
+        self.from_client.close();
+        self.priority_from_client.close();
+    }
+    async fn exec_loop(&mut self) -> crate::Result<()> {
         let mut frame_cnt = 1;
         loop {
             let mut buf: [u8; 1024] = [0; 1024];
-            select! {
+            select_biased! {
+                cmd = self.priority_from_client.recv().fuse() => match cmd {
+                    Ok(cmd) => {
+                        if self.process_command(cmd).await? {
+                            return Ok(());
+                        }
+                    }
+                    Err(_) => {
+                        // all the clients are dropped, the queued requests and signals are still sent
+                        while let Ok(cmd) = self.from_client.try_recv() {
+                            if self.process_command(cmd).await? {
+                                return Ok(());
+                            }
+                        }
+                        debug!("No client left, closing connection");
+                        self.close_stream().await?;
+                        return Ok(());
+                    },
+                },
                 n = self.stream.read(&mut buf).fuse() => match n {
                     Ok(n) => {
                         debug!("{} bytes read from socket", n);
                         if n == 0 {
-                            return Err("socket closed by peer.".into())
+                            if !self.buffer.is_empty() {
+                                return Err(FrameError::Truncated { received: self.buffer.len() }.into())
+                            }
+                            debug!("socket closed by peer");
+                            return Err(Error::ConnectionClosed)
                         }
+                        self.stats.record_received(n);
                         self.buffer.extend_from_slice(&buf[..n]);
                         loop {
                             match self.receive_frame() {
                                 Ok(frame) => match frame {
                                     Some(frame) => {
-                                        debug!("{} sender is full: {}, Sending frame to clients ............: {}", frame_cnt, self.to_client.is_full(), &frame);
-                                        //if self.to_client.is_full() {
-                                        //    error!("sender is full, capacity: {}", self.to_client.capacity());
-                                        //}
-                                        self.to_client.broadcast(frame).await?;
-                                        debug!("{} ............ SENT", frame_cnt);
+                                        self.stats.record_frame_received();
+                                        debug!("{} dispatching frame ............: {}", frame_cnt, &frame);
+                                        self.dispatch_frame(frame).await?;
+                                        debug!("{} ............ DISPATCHED", frame_cnt);
                                         frame_cnt += 1;
                                     }
                                     None => {
@@ -89,29 +475,233 @@ impl Connection {
                                     }
                                 }
                                 Err(e) => {
-                                    error!("read frame error {}", e);
-                                    break;
+                                    // there is no way to find next frame start in block framing
+                                    self.stats.record_parse_error();
+                                    error!("read frame error: {}, dropping connection", e);
+                                    return Err(e);
                                 }
                             }
                         }
                     },
                     Err(e) => {
                         error!("read socket error {}", e);
+                        return Err(e.into());
                     },
                 },
-                frame = self.from_client.recv().fuse() => match frame {
-                    Ok(frame) => {
-                        debug!("Frame to send from client: {}", &frame);
-                        self.send_frame(&frame).await?;
+                cmd = self.from_client.recv().fuse() => match cmd {
+                    Ok(cmd) => {
+                        if self.process_command(cmd).await? {
+                            return Ok(());
+                        }
                     }
-                    Err(e) => {
-                        error!("read frame error {}", e);
+                    Err(_) => {
+                        // all the clients are dropped
+                        debug!("No client left, closing connection");
+                        self.close_stream().await?;
+                        return Ok(());
                     },
                 }
             }
         }
     }
+    async fn close_stream(&mut self) -> crate::Result<()> {
+        self.stream.flush().await?;
+        futures::AsyncWriteExt::close(&mut self.stream).await?;
+        Ok(())
+    }
+    /// Returns `true`, if the connection was closed by the command
+    async fn process_command(&mut self, cmd: ConnectionCommand) -> crate::Result<bool> {
+        match cmd {
+            ConnectionCommand::SendFrame(frame) => {
+                debug!("Frame to send from client: {}", &frame);
+                let frame = self.to_request_protocol(frame);
+                self.send_frame(&frame).await?;
+            }
+            ConnectionCommand::CallRpcMethod { rq_id, frame, response_sender } => {
+                debug!("RPC call id: {} to send from client: {}", rq_id, &frame);
+                self.pending_rpc_calls.insert(rq_id, response_sender);
+                if let Err(e) = self.send_frame(&frame).await {
+                    self.pending_rpc_calls.remove(&rq_id);
+                    return Err(e);
+                }
+            }
+            ConnectionCommand::AbortRpcCall(rq_id) => {
+                if self.pending_rpc_calls.remove(&rq_id).is_some() {
+                    debug!("RPC call id: {} aborted", rq_id);
+                }
+            }
+            ConnectionCommand::Subscribe { path, method, sender, reply } => {
+                let id = self.next_subscription_id;
+                self.next_subscription_id += 1;
+                let status = match self.broker_subscriptions.get_mut(&(path.clone(), method.clone())) {
+                    None => {
+                        self.broker_subscriptions.insert((path.clone(), method.clone()), BrokerSubscription::Pending { owner: id, waiters: Vec::new() });
+                        SubscribeStatus::First
+                    }
+                    Some(BrokerSubscription::Pending { waiters, .. }) => {
+                        let (result_sender, result_receiver) = async_std::channel::bounded(1);
+                        waiters.push(result_sender);
+                        SubscribeStatus::Pending(result_receiver)
+                    }
+                    Some(BrokerSubscription::Active) => SubscribeStatus::Active,
+                };
+                debug!("Subscriber id: {} added, path: '{}' method: '{}'", id, path, method);
+                self.subscribers.insert(id, Subscriber { path, method, sender });
+                let _ = reply.try_send((id, status));
+            }
+            ConnectionCommand::BrokerSubscribed { path, method, error } => {
+                self.finish_broker_subscription(&(path, method), error);
+            }
+            ConnectionCommand::Unsubscribe { id, reply } => {
+                let count = self.subscribers.remove(&id).map(|subscriber| {
+                    let key = (subscriber.path, subscriber.method);
+                    let count = self.subscriber_count(&key.0, &key.1);
+                    match self.broker_subscriptions.get(&key) {
+                        Some(BrokerSubscription::Pending { owner, .. }) if *owner == id => {
+                            // the first subscriber gave up, broker subscription might be created anyway
+                            let error = RpcError::new(RpcErrorCode::MethodCallCancelled, "Subscription cancelled");
+                            self.finish_broker_subscription(&key, Some(error));
+                            0
+                        }
+                        _ => {
+                            if count == 0 {
+                                self.broker_subscriptions.remove(&key);
+                            }
+                            count
+                        }
+                    }
+                });
+                debug!("Subscriber id: {} removed, count: {:?}", id, count);
+                let _ = reply.try_send(count);
+            }
+            ConnectionCommand::Close { done } => {
+                debug!("Closing connection on client request");
+                self.close_stream().await?;
+                self.shutdown();
+                drop(done);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    async fn dispatch_frame(&mut self, frame: RpcFrame) -> crate::Result<()> {
+        if frame.meta.is_response() {
+            let rq_id = frame.meta.request_id().unwrap_or_default();
+            match self.pending_rpc_calls.remove(&rq_id) {
+                Some(response_sender) => {
+                    // receiver might be dropped already if the caller gave up
+                    let _ = response_sender.try_send(frame);
+                }
+                None => {
+                    // late response to timed out or cancelled call
+                    debug!("Ignoring response to request id: {}, no pending call", rq_id);
+                }
+            }
+            return Ok(())
+        }
+        if let (Some(request_protocols), Some(rq_id)) = (&mut self.request_protocols, frame.meta.request_id()) {
+            // requests of different callers can have the same ID, such response is sent
+            // in protocol of the later one, which the peer can read as well
+            request_protocols.insert(rq_id, frame.protocol);
+        }
+        if frame.meta.request_id().is_none() {
+            let path = frame.meta.shv_path().unwrap_or_default();
+            let method = frame.meta.method().unwrap_or_default();
+            let ids: Vec<SubscriptionId> = self.subscribers.iter()
+                .filter(|(_, subscriber)| subscriber.matches(path, method))
+                .map(|(id, _)| *id)
+                .collect();
+            if !ids.is_empty() {
+                for id in ids {
+                    // subscriber not reading signals must not block the connection
+                    match self.subscribers[&id].sender.try_send(frame.clone()) {
+                        Ok(_) => {}
+                        Err(TrySendError::Full(_)) => {
+                            self.stats.record_dropped_signal();
+                            warn!("Subscriber id: {} does not read signals, dropping: {}", id, &frame);
+                        }
+                        Err(TrySendError::Closed(_)) => {
+                            // dropped Subscription sends Unsubscribe
+                            debug!("Subscriber id: {} dropped", id);
+                        }
+                    }
+                }
+                return Ok(())
+            }
+        }
+        if let Err(e) = self.to_client.send(frame).await {
+            warn!("Dropping frame, no client is receiving: {}", e.into_inner());
+        }
+        Ok(())
+    }
+    /// Encode response in protocol of its request, if enabled by `set_reply_in_request_protocol()`
+    fn to_request_protocol(&mut self, frame: RpcFrame) -> RpcFrame {
+        let request_protocols = match &mut self.request_protocols {
+            Some(request_protocols) if frame.meta.is_response() => request_protocols,
+            _ => return frame,
+        };
+        let protocol = match frame.meta.request_id().and_then(|rq_id| request_protocols.remove(rq_id)) {
+            // Protocol is not PartialEq
+            Some(protocol) if protocol as u8 != frame.protocol as u8 => protocol,
+            _ => return frame,
+        };
+        let convert = || -> crate::Result<RpcFrame> {
+            let msg = frame.to_rpcmesage()?;
+            Ok(RpcFrame::from_rpcmessage(protocol, &msg)?)
+        };
+        match convert() {
+            Ok(converted) => converted,
+            Err(e) => {
+                warn!("Cannot convert response to request protocol: {}", e);
+                frame
+            }
+        }
+    }
+    /// Deliver result of broker subscription to subscribers waiting for it,
+    /// all the local subscribers of the signal are removed on error.
+    fn finish_broker_subscription(&mut self, key: &(String, String), error: Option<RpcError>) {
+        let waiters = match self.broker_subscriptions.remove(key) {
+            Some(BrokerSubscription::Pending { waiters, .. }) => waiters,
+            _ => Vec::new(),
+        };
+        match &error {
+            None => {
+                self.broker_subscriptions.insert(key.clone(), BrokerSubscription::Active);
+            }
+            Some(err) => {
+                warn!("Broker subscription path: '{}' method: '{}' failed: {}", key.0, key.1, err.message);
+                self.subscribers.retain(|_, subscriber| subscriber.path != key.0 || subscriber.method != key.1);
+            }
+        }
+        for waiter in waiters {
+            let _ = waiter.try_send(error.as_ref().map(|err| RpcError::new(err.code, &err.message)));
+        }
+    }
+    fn subscriber_count(&self, path: &str, method: &str) -> usize {
+        self.subscribers.values().filter(|subscriber| subscriber.path == path && subscriber.method == method).count()
+    }
     fn receive_frame(&mut self) -> crate::Result<Option<RpcFrame>> {
+        match self.framing {
+            Framing::Block => self.receive_block_frame(),
+            Framing::Serial => self.receive_serial_frame(),
+        }
+    }
+    fn receive_block_frame(&mut self) -> crate::Result<Option<RpcFrame>> {
+        let (frame_len, header_len) = match read_uint_data(&self.buffer[..]) {
+            None => return Ok(None),
+            Some((frame_len, header_len)) => (frame_len as usize, header_len),
+        };
+        if frame_len > self.max_frame_size {
+            return Err(FrameError::TooLarge { size: frame_len, max_size: self.max_frame_size }.into());
+        }
+        if frame_len == 0 {
+            return Err(FrameError::Malformed("Empty frame".into()).into());
+        }
+        if let Some(protocol) = self.buffer.get(header_len) {
+            if *protocol != Protocol::ChainPack as u8 && *protocol != Protocol::Cpon as u8 {
+                return Err(FrameError::Malformed(format!("Invalid protocol type: {}", protocol)).into());
+            }
+        }
         let buff = &self.buffer[..];
         match RpcFrame::parse(buff) {
             Ok(maybe_frame) => {
@@ -119,45 +709,125 @@ impl Connection {
                     None => { return Ok(None); }
                     Some((frame_len, frame)) => {
                         self.buffer.advance(frame_len);
-                        Connection::log_frame(&frame, LogFramePrompt::Receive);
+                        self.observe_frame(&frame, FrameDirection::Receive);
                         Ok(Some(frame))
                     }
                 }
             }
-            Err(e) => Err(e.into()),
+            Err(e) => Err(FrameError::Malformed(e.to_string()).into()),
+        }
+    }
+    fn receive_serial_frame(&mut self) -> crate::Result<Option<RpcFrame>> {
+        loop {
+            let (consumed, data) = crate::serial::decode_frame(&self.buffer[..]);
+            self.buffer.advance(consumed);
+            match data {
+                None => {
+                    // every byte can be escaped, CRC and delimiters are 10 bytes at most
+                    let max_encoded_size = 2 * self.max_frame_size + 10;
+                    if self.buffer.len() > max_encoded_size {
+                        self.stats.record_parse_error();
+                        warn!("{}, skipping to next frame", FrameError::TooLarge { size: self.buffer.len(), max_size: max_encoded_size });
+                        // buffer starts with STX here, find next one
+                        let skip = self.buffer[1 ..].iter().position(|b| *b == crate::serial::STX).map(|ix| ix + 1).unwrap_or(self.buffer.len());
+                        self.buffer.advance(skip);
+                        continue;
+                    }
+                    return Ok(None)
+                }
+                Some(Err(e)) => {
+                    // frame is dropped, try next one
+                    self.stats.record_parse_error();
+                    warn!("serial frame error: {}", e);
+                }
+                Some(Ok(data)) if data.len() > self.max_frame_size => {
+                    self.stats.record_parse_error();
+                    warn!("{}, frame dropped", FrameError::TooLarge { size: data.len(), max_size: self.max_frame_size });
+                }
+                Some(Ok(data)) => {
+                    // serial frame does not contain length, make block frame from it
+                    let mut block = Vec::with_capacity(data.len() + 8);
+                    ChainPackWriter::new(&mut block).write_uint_data(data.len() as u64)?;
+                    block.extend_from_slice(&data);
+                    match RpcFrame::parse(&block) {
+                        Ok(Some((_, frame))) => {
+                            self.observe_frame(&frame, FrameDirection::Receive);
+                            return Ok(Some(frame))
+                        }
+                        Ok(None) => {
+                            self.stats.record_parse_error();
+                            warn!("{}, frame dropped", FrameError::Malformed("Incomplete RPC frame in serial frame".into()));
+                        }
+                        Err(e) => {
+                            self.stats.record_parse_error();
+                            warn!("{}, frame dropped", FrameError::Malformed(e.to_string()));
+                        }
+                    }
+                }
+            }
         }
     }
 
-    async fn send_frame(&mut self, frame: &RpcFrame) -> crate::Result<()> {
-        Connection::log_frame(&frame, LogFramePrompt::Send);
-        let mut meta_data = Vec::new();
+    /// Protocol byte followed by meta and message data
+    pub(crate) fn frame_data(frame: &RpcFrame) -> crate::Result<Vec<u8>> {
+        let mut data = vec![frame.protocol as u8];
         match &frame.protocol {
             Protocol::ChainPack => {
-                let mut wr = ChainPackWriter::new(&mut meta_data);
+                let mut wr = ChainPackWriter::new(&mut data);
                 wr.write_meta(&frame.meta)?;
             }
             Protocol::Cpon => {
-                let mut wr = CponWriter::new(&mut meta_data);
+                let mut wr = CponWriter::new(&mut data);
                 wr.write_meta(&frame.meta)?;
             }
         }
-        let mut header = Vec::new();
-        let mut wr = ChainPackWriter::new(&mut header);
-        let msg_len = 1 + meta_data.len() + frame.data.len();
-        wr.write_uint_data(msg_len as u64)?;
-        header.push(frame.protocol as u8);
-        self.stream.write(&header).await?;
-        self.stream.write(&meta_data).await?;
-        self.stream.write(&frame.data).await?;
+        data.extend_from_slice(&frame.data);
+        Ok(data)
+    }
+
+    async fn send_frame(&mut self, frame: &RpcFrame) -> crate::Result<()> {
+        self.observe_frame(frame, FrameDirection::Send);
+        let data = Connection::frame_data(frame)?;
+        let sent_len = match self.framing {
+            Framing::Block => {
+                let mut header = Vec::new();
+                let mut wr = ChainPackWriter::new(&mut header);
+                wr.write_uint_data(data.len() as u64)?;
+                self.stream.write_all(&header).await?;
+                self.stream.write_all(&data).await?;
+                header.len() + data.len()
+            }
+            Framing::Serial => {
+                let encoded = crate::serial::encode_frame(&data);
+                self.stream.write_all(&encoded).await?;
+                encoded.len()
+            }
+        };
         // Ensure the encoded frame is written to the socket. The calls above
         // are to the buffered stream and writes. Calling `flush` writes the
         // remaining contents of the buffer to the socket.
         self.stream.flush().await?;
+        self.stats.record_sent(sent_len);
         Ok(())
     }
 
-    fn log_frame(frame: &RpcFrame, prompt: LogFramePrompt) {
-        let prompt_str = match prompt { LogFramePrompt::Send => "<===", LogFramePrompt::Receive => "===>" };
+    /// Frame data prefixed by its length
+    pub(crate) fn block_frame_data(frame: &RpcFrame) -> crate::Result<Vec<u8>> {
+        let data = Connection::frame_data(frame)?;
+        let mut block = Vec::with_capacity(data.len() + 8);
+        ChainPackWriter::new(&mut block).write_uint_data(data.len() as u64)?;
+        block.extend_from_slice(&data);
+        Ok(block)
+    }
+
+    fn observe_frame(&mut self, frame: &RpcFrame, direction: FrameDirection) {
+        Connection::log_frame(frame, direction);
+        for observer in self.observers.iter_mut() {
+            observer.on_frame(frame, direction);
+        }
+    }
+    fn log_frame(frame: &RpcFrame, direction: FrameDirection) {
+        let prompt_str = match direction { FrameDirection::Send => "<===", FrameDirection::Receive => "===>" };
         if frame.data.len() < 1024 {
             match frame.to_rpcmesage() {
                 Ok(rpcmessage) => {
@@ -172,3 +842,251 @@ impl Connection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::time::Duration;
+    #[cfg(unix)]
+    use async_std::os::unix::net::UnixStream;
+    #[cfg(unix)]
+    use async_std::prelude::*;
+    use async_std::task;
+    use chainpack::{ChainPackWriter, RpcMessage, RpcMessageMetaTags, Writer};
+    use chainpack::rpcframe::Protocol;
+    use futures::io::Cursor;
+    use crate::RpcFrame;
+    use crate::Error;
+    #[cfg(unix)]
+    use crate::client::Client;
+    use crate::connection::{read_uint_data, Connection, ConnectionCommand, FrameError, RequestProtocols};
+
+    /// Run connection over `data`, return messages received before the connection was dropped and exec error.
+    fn exec_on(data: Vec<u8>, max_frame_size: Option<usize>) -> (Vec<RpcMessage>, crate::Error) {
+        task::block_on(async {
+            let (mut connection, client) = Connection::new(Cursor::new(data), Protocol::ChainPack);
+            if let Some(max_frame_size) = max_frame_size {
+                connection.set_max_frame_size(max_frame_size);
+            }
+            let err = connection.exec().await.expect_err("stream end must be reported as error");
+            drop(connection);
+            let mut messages = Vec::new();
+            while let Ok(msg) = client.receive_message().await {
+                messages.push(msg);
+            }
+            (messages, err)
+        })
+    }
+
+    /// Connection over socket pair, the other end plays the broker
+    #[cfg(unix)]
+    fn connection_pair() -> crate::Result<(Connection, Client, UnixStream)> {
+        let (stream, peer) = UnixStream::pair()?;
+        let (connection, client) = Connection::new(stream, Protocol::ChainPack);
+        Ok((connection, client, peer))
+    }
+
+    #[test]
+    fn tst_read_uint_data() -> crate::Result<()> {
+        for n in [0u64, 1, 127, 128, 16383, 16384, 2097151, 2097152, 268435455, 268435456, u32::MAX as u64, u64::MAX] {
+            let mut data = Vec::new();
+            ChainPackWriter::new(&mut data).write_uint_data(n)?;
+            assert_eq!(read_uint_data(&data), Some((n, data.len())));
+            assert_eq!(read_uint_data(&data[.. data.len() - 1]), None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tst_valid_frames() -> crate::Result<()> {
+        let mut data = Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/b", "chng", Some(1.into())))?)?;
+        data.extend(Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/c", "chng", Some(2.into())))?)?);
+        let (messages, err) = exec_on(data, None);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].shv_path(), Some("a/c"));
+        assert!(matches!(err, Error::ConnectionClosed));
+        Ok(())
+    }
+
+    #[test]
+    fn tst_truncated_frame() -> crate::Result<()> {
+        let mut data = Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/b", "chng", Some(1.into())))?)?;
+        let frame2 = Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/c", "chng", Some(2.into())))?)?;
+        data.extend_from_slice(&frame2[.. frame2.len() - 2]);
+        let (messages, err) = exec_on(data, None);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(err, Error::Frame(FrameError::Truncated { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn tst_oversized_frame() -> crate::Result<()> {
+        // only header is sent, frame must be refused without waiting for the data
+        let mut data = Vec::new();
+        ChainPackWriter::new(&mut data).write_uint_data(1_000_000_000)?;
+        data.push(Protocol::ChainPack as u8);
+        let (messages, err) = exec_on(data, None);
+        assert!(messages.is_empty());
+        assert!(matches!(err, Error::Frame(FrameError::TooLarge { size: 1_000_000_000, .. })));
+
+        let data = Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/b", "chng", Some("x".repeat(100).into())))?)?;
+        let (messages, err) = exec_on(data, Some(64));
+        assert!(messages.is_empty());
+        assert!(matches!(err, Error::Frame(FrameError::TooLarge { max_size: 64, .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn tst_garbage() -> crate::Result<()> {
+        let mut data = Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/b", "chng", Some(1.into())))?)?;
+        data.extend_from_slice(&[0x05, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        data.extend(Connection::block_frame_data(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &RpcMessage::create_signal("a/c", "chng", Some(2.into())))?)?);
+        let (messages, err) = exec_on(data, None);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(err, Error::Frame(FrameError::Malformed(_))));
+
+        let (messages, err) = exec_on(vec![0x00], None);
+        assert!(messages.is_empty());
+        assert!(matches!(err, Error::Frame(FrameError::Malformed(_))));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tst_close_flushes_frames() -> crate::Result<()> {
+        task::block_on(async {
+            let (mut connection, client, mut peer) = connection_pair()?;
+            let exec = task::spawn(async move { connection.exec().await });
+            for i in 0 .. 10 {
+                client.send_message(&RpcMessage::create_signal("a/b", "chng", Some(i.into()))).await?;
+            }
+            client.close().await?;
+            exec.await?;
+            let mut data = Vec::new();
+            peer.read_to_end(&mut data).await?;
+            let mut cnt = 0;
+            let mut pos = 0;
+            while let Some((len, _)) = RpcFrame::parse(&data[pos ..])? {
+                pos += len;
+                cnt += 1;
+            }
+            assert_eq!(cnt, 10);
+            let err = client.send_message(&RpcMessage::create_signal("a/b", "chng", None)).await.expect_err("connection is closed");
+            assert!(matches!(err, Error::ConnectionClosed));
+            Ok(())
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tst_reply_in_request_protocol() -> crate::Result<()> {
+        task::block_on(async {
+            let (mut connection, client, mut peer) = connection_pair()?;
+            connection.set_reply_in_request_protocol(true);
+            let exec = task::spawn(async move { connection.exec().await });
+            let rq = RpcMessage::create_request("a/b", "get", None);
+            let frame = RpcFrame::from_rpcmessage(Protocol::Cpon, &rq)?;
+            peer.write_all(&Connection::block_frame_data(&frame)?).await?;
+            let rq = client.receive_message().await?;
+            let mut resp = rq.prepare_response()?;
+            resp.set_result(42.into());
+            client.send_message(&resp).await?;
+            client.send_message(&RpcMessage::create_signal("a/b", "chng", None)).await?;
+            client.close().await?;
+            exec.await?;
+            let mut data = Vec::new();
+            peer.read_to_end(&mut data).await?;
+            let (len, resp) = RpcFrame::parse(&data)?.expect("response frame");
+            assert!(matches!(resp.protocol, Protocol::Cpon));
+            assert_eq!(resp.to_rpcmesage()?.result().unwrap().as_int(), 42);
+            let (_, signal) = RpcFrame::parse(&data[len ..])?.expect("signal frame");
+            assert!(matches!(signal.protocol, Protocol::ChainPack));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn tst_request_protocols_evict_oldest() {
+        let mut request_protocols = RequestProtocols::default();
+        request_protocols.insert(5000, Protocol::Cpon);
+        for rq_id in 1 ..= RequestProtocols::MAX_LEN as i64 {
+            request_protocols.insert(rq_id, Protocol::ChainPack);
+        }
+        assert!(request_protocols.remove(5000).is_none());
+        assert!(request_protocols.remove(1).is_some());
+        assert!(request_protocols.remove(1).is_none());
+        assert_eq!(request_protocols.protocols.len(), request_protocols.order.len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tst_close_by_peer() -> crate::Result<()> {
+        task::block_on(async {
+            let (mut connection, client, peer) = connection_pair()?;
+            let exec = task::spawn(async move { connection.exec().await });
+            let call = {
+                let client = client.clone();
+                task::spawn(async move { client.call_rpc_method(RpcMessage::create_request("a/b", "get", None)).await })
+            };
+            task::sleep(Duration::from_millis(100)).await;
+            drop(peer);
+            assert!(exec.await.is_err());
+            let err = call.await.expect_err("pending call must fail");
+            assert!(matches!(err, Error::ConnectionClosed));
+            let err = client.receive_message().await.expect_err("receiver must get end of stream");
+            assert!(matches!(err, Error::ConnectionClosed));
+            Ok(())
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tst_dropped_call_removed() -> crate::Result<()> {
+        task::block_on(async {
+            let (mut connection, client, _peer) = connection_pair()?;
+            {
+                let call = client.call(RpcMessage::create_request("a/b", "get", None)).timeout(None).exec();
+                futures::pin_mut!(call);
+                while connection.from_client.is_empty() {
+                    assert!(futures::poll!(call.as_mut()).is_pending());
+                }
+            }
+            let cmd = connection.from_client.recv().await.map_err(|_| Error::ConnectionClosed)?;
+            connection.process_command(cmd).await?;
+            assert_eq!(connection.pending_rpc_calls.len(), 1);
+            let cmd = async_std::future::timeout(Duration::from_secs(1), connection.from_client.recv()).await
+                .map_err(|_| Error::Timeout(Duration::from_secs(1)))?
+                .map_err(|_| Error::ConnectionClosed)?;
+            assert!(matches!(cmd, ConnectionCommand::AbortRpcCall(_)));
+            connection.process_command(cmd).await?;
+            assert!(connection.pending_rpc_calls.is_empty());
+            Ok(())
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tst_close_on_clients_dropped() -> crate::Result<()> {
+        task::block_on(async {
+            let (mut connection, client, mut peer) = connection_pair()?;
+            let sender = client.to_sender();
+            // frames queued before the clients are dropped are sent
+            for i in 0 .. 5 {
+                client.send_message(&RpcMessage::create_signal("a/b", "chng", Some(i.into()))).await?;
+            }
+            drop(client);
+            drop(sender);
+            task::spawn(async move { connection.exec().await }).await?;
+            let mut data = Vec::new();
+            peer.read_to_end(&mut data).await?;
+            let mut cnt = 0;
+            let mut pos = 0;
+            while let Some((len, _)) = RpcFrame::parse(&data[pos ..])? {
+                pos += len;
+                cnt += 1;
+            }
+            assert_eq!(cnt, 5);
+            Ok(())
+        })
+    }
+}
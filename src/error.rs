@@ -0,0 +1,176 @@
+use std::fmt;
+use std::time::Duration;
+use chainpack::ReadError;
+use chainpack::rpcmessage::{RpcError, RpcErrorCode};
+use crate::connection::FrameError;
+
+/// Error returned by most functions.
+///
+/// Variants let callers distinguish connection problems, error responses from peer,
+/// login failure, requests which cannot be handled by `ShvTree` and journal corruption.
+/// `to_rpc_error()` converts the error to RPC error sent back in response.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// Data cannot be deserialized by chainpack
+    ChainPack(String),
+    /// Received byte stream cannot be parsed to frames, the connection is dropped
+    Frame(FrameError),
+    /// Connection is closed, pending RPC calls and receivers get this error
+    ConnectionClosed,
+    /// Invalid connection parameters or connection setup failure (URL, TLS, WebSocket, serial port)
+    Connection(String),
+    /// Response didn't arrive in time
+    Timeout(Duration),
+    /// RPC call was cancelled by `CancelHandle`
+    Cancelled,
+    /// Error response sent by the peer
+    Rpc(RpcError),
+    /// Response does not contain result of expected type
+    InvalidResult(String),
+    Login(String),
+    /// Request is not valid RPC request
+    InvalidRequest(String),
+    /// Node or method does not exist
+    MethodNotFound(String),
+    /// Request params are missing or invalid
+    InvalidParams(String),
+    /// Caller access grant is lower than the method requires
+    PermissionDenied(String),
+    /// Journal files are inconsistent or corrupted
+    Journal(String),
+    Other(String),
+}
+
+impl Error {
+    pub fn rpc_error_code(&self) -> RpcErrorCode {
+        match self {
+            Error::Rpc(e) => e.code,
+            Error::ChainPack(_) => RpcErrorCode::ParseError,
+            Error::Timeout(_) => RpcErrorCode::MethodCallTimeout,
+            Error::Cancelled => RpcErrorCode::MethodCallCancelled,
+            Error::InvalidRequest(_) => RpcErrorCode::InvalidRequest,
+            Error::MethodNotFound(_) => RpcErrorCode::MethodNotFound,
+            Error::InvalidParams(_) => RpcErrorCode::InvalidParams,
+            Error::PermissionDenied(_) => RpcErrorCode::PermissionDenied,
+            Error::Io(_) | Error::Frame(_) | Error::ConnectionClosed | Error::Connection(_) | Error::Journal(_) => RpcErrorCode::InternalError,
+            Error::InvalidResult(_) | Error::Login(_) | Error::Other(_) => RpcErrorCode::MethodCallException,
+        }
+    }
+    /// RPC error to be set to response of request, which failed with this error
+    pub fn to_rpc_error(&self) -> RpcError {
+        match self {
+            Error::Rpc(e) => RpcError::new(e.code, &e.message),
+            e => RpcError::new(e.rpc_error_code(), &e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::ChainPack(msg) => write!(f, "ChainPack error: {}", msg),
+            Error::Frame(e) => write!(f, "{}", e),
+            Error::ConnectionClosed => write!(f, "Connection closed"),
+            Error::Connection(msg) => write!(f, "Connection error: {}", msg),
+            Error::Timeout(timeout) => write!(f, "Response didn't arrive within {:?}", timeout),
+            Error::Cancelled => write!(f, "RPC call cancelled"),
+            Error::Rpc(e) => write!(f, "RPC error {:?}: {}", e.code, e.message),
+            Error::InvalidResult(msg) => write!(f, "Invalid result: {}", msg),
+            Error::Login(msg) => write!(f, "Login error: {}", msg),
+            Error::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+            Error::MethodNotFound(msg) => write!(f, "Method not found: {}", msg),
+            Error::InvalidParams(msg) => write!(f, "Invalid params: {}", msg),
+            Error::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            Error::Journal(msg) => write!(f, "Journal error: {}", msg),
+            Error::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Frame(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+impl From<FrameError> for Error {
+    fn from(e: FrameError) -> Self {
+        Error::Frame(e)
+    }
+}
+impl From<ReadError> for Error {
+    fn from(e: ReadError) -> Self {
+        Error::ChainPack(e.to_string())
+    }
+}
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        Error::Rpc(e)
+    }
+}
+/// Errors of `chainpack` functions returning boxed error, they are not necessarily parse errors
+impl From<Box<dyn std::error::Error + Send + Sync>> for Error {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::Connection(format!("Invalid URL: {}", e))
+    }
+}
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Self {
+        Error::Other(e.to_string())
+    }
+}
+impl From<futures_rustls::rustls::Error> for Error {
+    fn from(e: futures_rustls::rustls::Error) -> Self {
+        Error::Connection(format!("TLS error: {}", e))
+    }
+}
+impl From<async_tungstenite::tungstenite::Error> for Error {
+    fn from(e: async_tungstenite::tungstenite::Error) -> Self {
+        Error::Connection(format!("WebSocket error: {}", e))
+    }
+}
+#[cfg(unix)]
+impl From<nix::Error> for Error {
+    fn from(e: nix::Error) -> Self {
+        Error::Io(e.into())
+    }
+}
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::InvalidParams(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chainpack::rpcmessage::RpcErrorCode;
+    use crate::Error;
+
+    #[test]
+    fn tst_rpc_error_code() {
+        assert!(matches!(Error::MethodNotFound("foo".into()).rpc_error_code(), RpcErrorCode::MethodNotFound));
+        assert!(matches!(Error::InvalidParams("foo".into()).rpc_error_code(), RpcErrorCode::InvalidParams));
+        assert!(matches!(Error::Other("foo".into()).rpc_error_code(), RpcErrorCode::MethodCallException));
+        let err = Error::from(std::io::Error::new(std::io::ErrorKind::NotFound, "file not found"));
+        assert!(matches!(err.rpc_error_code(), RpcErrorCode::InternalError));
+        assert!(err.to_rpc_error().message.contains("file not found"));
+        let boxed: Box<dyn std::error::Error + Send + Sync> = "invalid params".into();
+        assert!(matches!(Error::from(boxed).rpc_error_code(), RpcErrorCode::MethodCallException));
+    }
+}
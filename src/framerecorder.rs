@@ -0,0 +1,386 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+use chainpack::{ChainPackWriter, DateTime, RpcMessage, RpcMessageMetaTags, RpcValue, Writer};
+use chainpack::rpcframe::{Protocol, RpcFrame};
+use futures::{AsyncRead, AsyncWrite};
+use log::warn;
+use crate::connection::{read_uint_data, Connection};
+use crate::Error;
+
+/// Direction of frame passing through `Connection`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameDirection {
+    Send,
+    Receive,
+}
+impl FrameDirection {
+    pub fn to_str(&self) -> &str {
+        match self {
+            FrameDirection::Send => "send",
+            FrameDirection::Receive => "receive",
+        }
+    }
+    pub fn from_str(s: &str) -> crate::Result<FrameDirection> {
+        match s {
+            "send" => Ok(FrameDirection::Send),
+            "receive" => Ok(FrameDirection::Receive),
+            _ => Err(Error::Other(format!("Invalid frame direction: '{}'", s))),
+        }
+    }
+}
+
+/// Gets every frame sent or received by `Connection`, see `Connection::add_frame_observer`.
+///
+/// Observer is called from the connection message loop, it should not block.
+pub trait FrameObserver: Send {
+    fn on_frame(&mut self, frame: &RpcFrame, direction: FrameDirection);
+}
+
+/// Encoding of the recording file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RecordFormat {
+    /// Every record is ChainPack value prefixed by its length
+    ChainPack,
+    /// Every record is CPON value on single line
+    Cpon,
+}
+impl RecordFormat {
+    /// `Cpon` for files with `.cpon` extension, `ChainPack` otherwise
+    pub fn from_path(path: &Path) -> RecordFormat {
+        match path.extension() {
+            Some(ext) if ext == "cpon" => RecordFormat::Cpon,
+            _ => RecordFormat::ChainPack,
+        }
+    }
+}
+
+/// Frame read from recording
+pub struct RecordedFrame {
+    pub timestamp: DateTime,
+    pub direction: FrameDirection,
+    pub frame: RpcFrame,
+}
+/// Placeholder of password and token in recorded `login` request
+const REDACTED_PASSWORD: &str = "***";
+
+impl RecordedFrame {
+    /// Record is `[timestamp, direction, protocol, message]`, message is stored as block frame blob,
+    /// if the frame cannot be decoded.
+    fn to_rpcvalue(frame: &RpcFrame, direction: FrameDirection) -> crate::Result<RpcValue> {
+        let payload = match frame.to_rpcmesage() {
+            Ok(msg) => RecordedFrame::redact_login(msg).as_rpcvalue().clone(),
+            Err(_) => RpcValue::from(&Connection::block_frame_data(frame)?[..]),
+        };
+        let record = vec![
+            RpcValue::from(DateTime::now()),
+            RpcValue::from(direction.to_str()),
+            RpcValue::from(frame.protocol as i32),
+            payload,
+        ];
+        Ok(RpcValue::from(record))
+    }
+    /// Replace password of `login` request, it is plain password or token for PLAIN and TOKEN login
+    fn redact_login(mut msg: RpcMessage) -> RpcMessage {
+        if !msg.is_request() || msg.method() != Some("login") {
+            return msg;
+        }
+        let mut params = match msg.params() {
+            Some(params) if params.is_map() => params.as_map().clone(),
+            _ => return msg,
+        };
+        let mut login = match params.get("login") {
+            Some(login) if login.is_map() => login.as_map().clone(),
+            _ => return msg,
+        };
+        if login.contains_key("password") {
+            login.insert("password".into(), RpcValue::from(REDACTED_PASSWORD));
+            params.insert("login".into(), RpcValue::from(login));
+            msg.set_params(RpcValue::from(params));
+        }
+        msg
+    }
+    fn from_rpcvalue(rv: &RpcValue) -> crate::Result<RecordedFrame> {
+        let invalid = || Error::Other(format!("Invalid frame record: {}", rv));
+        if !rv.is_list() {
+            return Err(invalid());
+        }
+        let record = rv.as_list();
+        if record.len() != 4 {
+            return Err(invalid());
+        }
+        let protocol = if record[2].as_int() == Protocol::Cpon as i64 { Protocol::Cpon } else { Protocol::ChainPack };
+        let frame = if record[3].is_blob() {
+            match RpcFrame::parse(record[3].as_blob()).map_err(|e| Error::ChainPack(e.to_string()))? {
+                Some((_, frame)) => frame,
+                None => return Err(invalid()),
+            }
+        } else {
+            let msg = RpcMessage::from_rpcvalue(record[3].clone()).map_err(|_| invalid())?;
+            RpcFrame::from_rpcmessage(protocol, &msg)?
+        };
+        Ok(RecordedFrame {
+            timestamp: record[0].as_datetime(),
+            direction: FrameDirection::from_str(record[1].as_str())?,
+            frame,
+        })
+    }
+}
+
+/// Writes all the frames with timestamp and direction to file.
+///
+/// File is opened in append mode, so the recording survives reconnects. Records are written
+/// by background thread, so the connection message loop is not blocked by file I/O,
+/// the file is flushed periodically and when the recorder is dropped.
+pub struct FrameRecorder {
+    format: RecordFormat,
+    // None when dropped
+    records: Option<mpsc::Sender<Vec<u8>>>,
+    writer: Option<thread::JoinHandle<()>>,
+    failed: bool,
+}
+/// Maximal time the records can stay in write buffer
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+impl FrameRecorder {
+    pub fn create(path: impl AsRef<Path>, format: RecordFormat) -> crate::Result<FrameRecorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let (records, receiver) = mpsc::channel();
+        let writer = thread::Builder::new()
+            .name("frame-recorder".into())
+            .spawn(move || FrameRecorder::write_records(BufWriter::new(file), receiver))?;
+        Ok(FrameRecorder {
+            format,
+            records: Some(records),
+            writer: Some(writer),
+            failed: false,
+        })
+    }
+    pub fn record(&mut self, frame: &RpcFrame, direction: FrameDirection) -> crate::Result<()> {
+        let record = RecordedFrame::to_rpcvalue(frame, direction)?;
+        let data = match self.format {
+            RecordFormat::ChainPack => {
+                let data = record.to_chainpack();
+                let mut block = Vec::with_capacity(data.len() + 8);
+                ChainPackWriter::new(&mut block).write_uint_data(data.len() as u64)?;
+                block.extend_from_slice(&data);
+                block
+            }
+            RecordFormat::Cpon => format!("{}\n", record.to_cpon()).into_bytes(),
+        };
+        match &self.records {
+            Some(records) => records.send(data).map_err(|_| Error::Other("Frame recording writer stopped".into())),
+            None => Err(Error::Other("Frame recorder closed".into())),
+        }
+    }
+    /// Runs in writer thread till the recorder is dropped or write fails
+    fn write_records(mut writer: BufWriter<File>, records: mpsc::Receiver<Vec<u8>>) {
+        let mut last_flush = Instant::now();
+        loop {
+            match records.recv_timeout(FLUSH_INTERVAL) {
+                Ok(data) => {
+                    if let Err(e) = writer.write_all(&data) {
+                        warn!("Frame recording write error: {}", e);
+                        return;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                if let Err(e) = writer.flush() {
+                    warn!("Frame recording flush error: {}", e);
+                    return;
+                }
+                last_flush = Instant::now();
+            }
+        }
+        if let Err(e) = writer.flush() {
+            warn!("Frame recording flush error: {}", e);
+        }
+    }
+}
+impl Drop for FrameRecorder {
+    fn drop(&mut self) {
+        // closing the channel makes the writer flush and exit
+        self.records = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+impl FrameObserver for FrameRecorder {
+    fn on_frame(&mut self, frame: &RpcFrame, direction: FrameDirection) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.record(frame, direction) {
+            warn!("Frame recording error: {}, recording stopped", e);
+            self.failed = true;
+        }
+    }
+}
+
+/// Read all the frames from recording
+pub fn read_recording(path: impl AsRef<Path>, format: RecordFormat) -> crate::Result<Vec<RecordedFrame>> {
+    let data = std::fs::read(path)?;
+    let mut frames = Vec::new();
+    match format {
+        RecordFormat::ChainPack => {
+            let mut rest = &data[..];
+            while !rest.is_empty() {
+                let (len, header_len) = read_uint_data(rest)
+                    .ok_or_else(|| Error::Other("Truncated frame record header".into()))?;
+                let end = header_len + len as usize;
+                if rest.len() < end {
+                    return Err(Error::Other("Truncated frame record".into()));
+                }
+                let rv = RpcValue::from_chainpack(&rest[header_len .. end])?;
+                frames.push(RecordedFrame::from_rpcvalue(&rv)?);
+                rest = &rest[end ..];
+            }
+        }
+        RecordFormat::Cpon => {
+            let text = std::str::from_utf8(&data)?;
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                let rv = RpcValue::from_cpon(line)?;
+                frames.push(RecordedFrame::from_rpcvalue(&rv)?);
+            }
+        }
+    }
+    Ok(frames)
+}
+
+/// Stream feeding received frames of recording to `Connection`, frames sent by connection are discarded.
+///
+/// Connection created over this stream behaves like the recorded one, when the recording
+/// is replayed, `Connection::exec` returns `Error::ConnectionClosed`.
+pub struct ReplayStream {
+    data: Vec<u8>,
+    pos: usize,
+}
+impl ReplayStream {
+    pub fn new(frames: &[RecordedFrame]) -> crate::Result<ReplayStream> {
+        let mut data = Vec::new();
+        for recorded in frames.iter().filter(|recorded| recorded.direction == FrameDirection::Receive) {
+            data.extend_from_slice(&Connection::block_frame_data(&recorded.frame)?);
+        }
+        Ok(ReplayStream { data, pos: 0 })
+    }
+}
+impl AsyncRead for ReplayStream {
+    fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[.. n].copy_from_slice(&self.data[self.pos .. self.pos + n]);
+        self.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+impl AsyncWrite for ReplayStream {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::task;
+    use chainpack::{RpcMessage, RpcMessageMetaTags, RpcValue};
+    use chainpack::rpcframe::{Protocol, RpcFrame};
+    use chainpack::rpcvalue::Map;
+    use crate::{Connection, Error};
+    use crate::framerecorder::{read_recording, FrameDirection, FrameObserver, FrameRecorder, RecordFormat, ReplayStream};
+
+    #[test]
+    fn tst_record_and_replay() -> crate::Result<()> {
+        for format in [RecordFormat::ChainPack, RecordFormat::Cpon] {
+            let path = std::env::temp_dir().join(format!("shvapp-tst-record-{}-{:?}", std::process::id(), format));
+            let _ = std::fs::remove_file(&path);
+            {
+                let mut recorder = FrameRecorder::create(&path, format)?;
+                let rq = RpcMessage::create_request("test", "get", None);
+                recorder.on_frame(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &rq)?, FrameDirection::Send);
+                let mut resp = rq.prepare_response()?;
+                resp.set_result(42.into());
+                recorder.on_frame(&RpcFrame::from_rpcmessage(Protocol::Cpon, &resp)?, FrameDirection::Receive);
+                let signal = RpcMessage::create_signal("test", "chng", Some("hello".into()));
+                recorder.on_frame(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &signal)?, FrameDirection::Receive);
+            }
+            let frames = read_recording(&path, format)?;
+            std::fs::remove_file(&path)?;
+            assert_eq!(frames.len(), 3);
+            assert_eq!(frames[0].direction, FrameDirection::Send);
+            assert_eq!(frames[0].frame.to_rpcmesage()?.method(), Some("get"));
+            assert!(matches!(frames[1].frame.protocol, Protocol::Cpon));
+            assert_eq!(frames[1].frame.to_rpcmesage()?.result().unwrap().as_int(), 42);
+
+            task::block_on(async {
+                let (mut connection, client) = Connection::new(ReplayStream::new(&frames)?, Protocol::ChainPack);
+                assert!(matches!(connection.exec().await, Err(Error::ConnectionClosed)));
+                drop(connection);
+                // response to request, which was not sent by this client, is dropped
+                let msg = client.receive_message().await?;
+                assert_eq!(msg.method(), Some("chng"));
+                assert_eq!(msg.params().unwrap().as_str(), "hello");
+                assert!(client.receive_message().await.is_err());
+                crate::Result::Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tst_record_flushed_periodically() -> crate::Result<()> {
+        let path = std::env::temp_dir().join(format!("shvapp-tst-record-flush-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut recorder = FrameRecorder::create(&path, RecordFormat::ChainPack)?;
+        let signal = RpcMessage::create_signal("test", "chng", Some(1.into()));
+        recorder.on_frame(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &signal)?, FrameDirection::Receive);
+        std::thread::sleep(std::time::Duration::from_millis(2500));
+        // recorder is still alive
+        let frames = read_recording(&path, RecordFormat::ChainPack)?;
+        drop(recorder);
+        std::fs::remove_file(&path)?;
+        assert_eq!(frames.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn tst_login_password_not_recorded() -> crate::Result<()> {
+        for format in [RecordFormat::ChainPack, RecordFormat::Cpon] {
+            let path = std::env::temp_dir().join(format!("shvapp-tst-record-login-{}-{:?}", std::process::id(), format));
+            let _ = std::fs::remove_file(&path);
+            {
+                let mut login = Map::new();
+                login.insert("user".into(), RpcValue::from("user"));
+                login.insert("password".into(), RpcValue::from("secret-token"));
+                login.insert("type".into(), RpcValue::from("TOKEN"));
+                let mut params = Map::new();
+                params.insert("login".into(), RpcValue::from(login));
+                let rq = RpcMessage::create_request("", "login", Some(RpcValue::from(params)));
+                let mut recorder = FrameRecorder::create(&path, format)?;
+                recorder.on_frame(&RpcFrame::from_rpcmessage(Protocol::ChainPack, &rq)?, FrameDirection::Send);
+            }
+            let data = std::fs::read(&path)?;
+            let frames = read_recording(&path, format)?;
+            std::fs::remove_file(&path)?;
+            assert!(!String::from_utf8_lossy(&data).contains("secret-token"));
+            let msg = frames[0].frame.to_rpcmesage()?;
+            let login = msg.params().unwrap().as_map().get("login").unwrap().as_map();
+            assert_eq!(login.get("password").unwrap().as_str(), "***");
+            assert_eq!(login.get("user").unwrap().as_str(), "user");
+        }
+        Ok(())
+    }
+}
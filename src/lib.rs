@@ -1,12 +1,22 @@
 pub use chainpack::rpcframe::RpcFrame;
-pub use connection::Connection;
+pub use connection::{Connection, ConnectionCommand, ConnectionStats, Framing, FrameError, SubscribeStatus};
+pub use error::Error;
+pub use throttle::ClientLimits;
 
+mod error;
 mod connection;
+mod throttle;
 pub mod client;
+pub mod tls;
+pub mod serial;
+pub mod websocket;
+pub mod framerecorder;
 
 pub mod utils;
 pub mod shvtree;
 pub mod shvfsnode;
+pub mod shvconnectionnode;
+pub mod shvpropertynode;
 pub mod shvjournal;
 pub mod shvlog;
 
@@ -15,19 +25,6 @@ pub mod shvlog;
 /// Used if no port is specified.
 pub const DEFAULT_PORT: &str = "3755";
 
-/// Error returned by most functions.
-///
-/// When writing a real application, one might want to consider a specialized
-/// error handling crate or defining an error type as an `enum` of causes.
-/// However, for our example, using a boxed `std::error::Error` is sufficient.
-///
-/// For performance reasons, boxing is avoided in any hot path. For example, in
-/// `parse`, a custom error `enum` is defined. This is because the error is hit
-/// and handled during normal execution when a partial frame is received on a
-/// socket. `std::error::Error` is implemented for `parse::Error` which allows
-/// it to be converted to `Box<dyn std::error::Error>`.
-pub type Error = Box<dyn std::error::Error + Send + Sync>;
-
 /// A specialized `Result` type for mini-redis operations.
 ///
 /// This is defined as a convenience.
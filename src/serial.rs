@@ -0,0 +1,245 @@
+//! SHV serial framing
+//!
+//! Frame is sent as `STX <escaped data> ETX <escaped CRC32>`, where data is
+//! protocol byte followed by meta and message data (no length prefix) and CRC32
+//! of unescaped data is sent big-endian. `ATX` aborts the frame being received.
+
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use async_io::Async;
+#[cfg(unix)]
+use nix::sys::termios;
+#[cfg(unix)]
+use crate::Error;
+use crate::connection::FrameError;
+
+pub const STX: u8 = 0xA2;
+pub const ETX: u8 = 0xA3;
+pub const ATX: u8 = 0xA4;
+pub const ESC: u8 = 0xAA;
+
+const ESC_STX: u8 = 0x02;
+const ESC_ETX: u8 = 0x03;
+const ESC_ATX: u8 = 0x04;
+const ESC_ESC: u8 = 0x0A;
+
+pub const DEFAULT_BAUD_RATE: u32 = 115200;
+
+#[derive(Clone, Debug)]
+pub struct SerialParams {
+    pub baud_rate: u32,
+}
+impl Default for SerialParams {
+    fn default() -> Self {
+        SerialParams {
+            baud_rate: DEFAULT_BAUD_RATE,
+        }
+    }
+}
+
+/// Open serial device in raw mode
+#[cfg(unix)]
+pub fn open(path: &str, params: &SerialParams) -> crate::Result<Async<File>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(nix::libc::O_NOCTTY)
+        .open(path)?;
+    let fd = file.as_raw_fd();
+    let mut tio = termios::tcgetattr(fd)?;
+    termios::cfmakeraw(&mut tio);
+    termios::cfsetspeed(&mut tio, baud_rate(params.baud_rate)?)?;
+    termios::tcsetattr(fd, termios::SetArg::TCSANOW, &tio)?;
+    Ok(Async::new(file)?)
+}
+
+#[cfg(unix)]
+fn baud_rate(rate: u32) -> crate::Result<termios::BaudRate> {
+    use termios::BaudRate;
+    let br = match rate {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        _ => return Err(Error::Connection(format!("Unsupported baud rate: {}", rate))),
+    };
+    Ok(br)
+}
+
+fn push_escaped(out: &mut Vec<u8>, b: u8) {
+    match b {
+        STX => { out.push(ESC); out.push(ESC_STX); }
+        ETX => { out.push(ESC); out.push(ESC_ETX); }
+        ATX => { out.push(ESC); out.push(ESC_ATX); }
+        ESC => { out.push(ESC); out.push(ESC_ESC); }
+        b => out.push(b),
+    }
+}
+
+pub fn encode_frame(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 10);
+    out.push(STX);
+    for b in data {
+        push_escaped(&mut out, *b);
+    }
+    out.push(ETX);
+    for b in &crc32fast::hash(data).to_be_bytes() {
+        push_escaped(&mut out, *b);
+    }
+    out
+}
+
+enum Token {
+    Byte(u8),
+    Stx,
+    Etx,
+    Atx,
+}
+
+/// Read one unescaped byte or control character, None if more data is needed.
+fn next_token(buf: &[u8], pos: &mut usize) -> Option<crate::Result<Token>> {
+    let b = *buf.get(*pos)?;
+    let token = match b {
+        STX => Token::Stx,
+        ETX => Token::Etx,
+        ATX => Token::Atx,
+        ESC => {
+            let b = *buf.get(*pos + 1)?;
+            *pos += 1;
+            match b {
+                ESC_STX => Token::Byte(STX),
+                ESC_ETX => Token::Byte(ETX),
+                ESC_ATX => Token::Byte(ATX),
+                ESC_ESC => Token::Byte(ESC),
+                b => {
+                    *pos += 1;
+                    return Some(Err(FrameError::Malformed(format!("Invalid escape sequence: 0x{:02X}", b)).into()))
+                }
+            }
+        }
+        b => Token::Byte(b),
+    };
+    *pos += 1;
+    Some(Ok(token))
+}
+
+/// Find next frame in `buf`.
+///
+/// Returns number of bytes, which can be dropped from `buf`, and frame data if complete frame was found.
+/// Bytes outside of frames and aborted frames are skipped, corrupted frame is returned as error.
+pub fn decode_frame(buf: &[u8]) -> (usize, Option<crate::Result<Vec<u8>>>) {
+    let mut start = match buf.iter().position(|b| *b == STX) {
+        None => return (buf.len(), None),
+        Some(ix) => ix,
+    };
+    'frame: loop {
+        let mut pos = start + 1;
+        let mut data = Vec::new();
+        loop {
+            match next_token(buf, &mut pos) {
+                None => return (start, None),
+                Some(Err(e)) => return (pos, Some(Err(e))),
+                Some(Ok(Token::Byte(b))) => data.push(b),
+                Some(Ok(Token::Etx)) => break,
+                Some(Ok(Token::Stx)) => {
+                    // previous frame was not finished, start again from this STX
+                    start = pos - 1;
+                    continue 'frame;
+                }
+                Some(Ok(Token::Atx)) => {
+                    match buf[pos ..].iter().position(|b| *b == STX) {
+                        None => return (buf.len(), None),
+                        Some(ix) => { start = pos + ix; continue 'frame; }
+                    }
+                }
+            }
+        }
+        let mut crc = [0u8; 4];
+        for crc_byte in crc.iter_mut() {
+            match next_token(buf, &mut pos) {
+                None => return (start, None),
+                Some(Err(e)) => return (pos, Some(Err(e))),
+                Some(Ok(Token::Byte(b))) => *crc_byte = b,
+                Some(Ok(Token::Stx)) => {
+                    start = pos - 1;
+                    continue 'frame;
+                }
+                Some(Ok(_)) => return (pos, Some(Err(FrameError::Malformed("Control character in frame CRC".into()).into()))),
+            }
+        }
+        let crc = u32::from_be_bytes(crc);
+        if crc != crc32fast::hash(&data) {
+            return (pos, Some(Err(FrameError::Malformed(format!("Frame CRC error, expected: 0x{:08X}, got: 0x{:08X}", crc32fast::hash(&data), crc)).into())))
+        }
+        return (pos, Some(Ok(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::{decode_frame, encode_frame, ATX, ESC, ETX, STX};
+
+    #[test]
+    fn tst_encode_decode() -> crate::Result<()> {
+        let data = vec![1, STX, 2, ETX, 3, ATX, 4, ESC, 5];
+        let encoded = encode_frame(&data);
+        assert_eq!(encoded.iter().filter(|b| **b == STX).count(), 1);
+        assert_eq!(encoded.iter().filter(|b| **b == ETX).count(), 1);
+        assert!(!encoded.contains(&ATX));
+        let (consumed, frame) = decode_frame(&encoded);
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(frame.unwrap()?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn tst_decode_partial() -> crate::Result<()> {
+        let encoded = encode_frame(b"hello");
+        for n in 1 .. encoded.len() {
+            let (consumed, frame) = decode_frame(&encoded[.. n]);
+            assert_eq!(consumed, 0);
+            assert!(frame.is_none());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tst_decode_garbage_and_abort() -> crate::Result<()> {
+        let mut buf = vec![0x11, 0x22];
+        buf.extend_from_slice(&[STX, 0x33, ATX, 0x44]);
+        buf.extend(encode_frame(b"abc"));
+        let (consumed, frame) = decode_frame(&buf);
+        assert_eq!(consumed, buf.len());
+        assert_eq!(frame.unwrap()?, b"abc".to_vec());
+
+        let (consumed, frame) = decode_frame(&[0x11, 0x22, 0x33]);
+        assert_eq!(consumed, 3);
+        assert!(frame.is_none());
+
+        let mut buf = vec![STX, 0x55];
+        buf.extend(encode_frame(b"xyz"));
+        let (consumed, frame) = decode_frame(&buf);
+        assert_eq!(consumed, buf.len());
+        assert_eq!(frame.unwrap()?, b"xyz".to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn tst_decode_crc_error() {
+        let mut encoded = encode_frame(b"hello");
+        encoded[2] = b'a';
+        let (consumed, frame) = decode_frame(&encoded);
+        assert_eq!(consumed, encoded.len());
+        assert!(frame.unwrap().is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use chainpack::metamethod::{Flag, MetaMethod, Signature};
+use chainpack::{RpcMessage, RpcMessageMetaTags, RpcValue};
+use crate::connection::ConnectionStats;
+use crate::Error;
+use crate::shvtree::{ProcessRequestResult, ShvNode, ShvNodeHelper};
+
+/// Exports connection traffic counters, usually mounted as `.app/connection`.
+///
+/// Every counter is a child node with `get` method, `get` on the node itself returns all of them as map.
+pub struct ConnectionNode {
+    stats: Arc<ConnectionStats>,
+}
+impl ConnectionNode {
+    pub fn new(stats: Arc<ConnectionStats>) -> Self {
+        Self {
+            stats,
+        }
+    }
+}
+
+impl ShvNode for ConnectionNode {
+    fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> ProcessRequestResult {
+        let method = request.method().ok_or_else(|| Error::InvalidRequest("Empty method".into()))?;
+        const M_DIR: &str = "dir";
+        const M_LS: &str = "ls";
+        const M_GET: &str = "get";
+        if !shv_path.is_empty() && !ConnectionStats::KEYS.contains(&shv_path) {
+            return Err(Error::MethodNotFound(format!("Invalid path '{}'", shv_path)));
+        }
+        #[allow(non_snake_case)]
+        if method == M_DIR {
+            let DIR = ShvNodeHelper::new_method_dir();
+            let LS = ShvNodeHelper::new_method_ls();
+            let GET = MetaMethod { name: M_GET.into(), signature: Signature::RetVoid, flags: Flag::IsGetter.into(), access_grant: RpcValue::from("rd"), description: "Counter value".into() };
+            let methods = vec![DIR, LS, GET];
+            return Ok(Some(ShvNodeHelper::dir_result(methods.iter(), request.params())));
+        }
+        if method == M_LS {
+            let dirs: Vec<(String, bool)> = if shv_path.is_empty() {
+                ConnectionStats::KEYS.iter().map(|key| (key.to_string(), false)).collect()
+            } else {
+                Vec::new()
+            };
+            return Ok(Some(ShvNodeHelper::ls_result(dirs.iter(), request.params())));
+        }
+        if method == M_GET {
+            if shv_path.is_empty() {
+                return Ok(Some(self.stats.to_rpcvalue()));
+            }
+            return Ok(self.stats.value(shv_path));
+        }
+        Err(Error::MethodNotFound(format!("Unknown method '{}' on path '{}'", method, shv_path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use chainpack::RpcMessage;
+    use crate::connection::ConnectionStats;
+    use crate::shvconnectionnode::ConnectionNode;
+    use crate::shvtree::{AccessLevel, ShvTree};
+
+    #[test]
+    fn tst_connection_node() -> crate::Result<()> {
+        let stats = Arc::new(ConnectionStats::default());
+        stats.frames_sent.fetch_add(5, Ordering::Relaxed);
+        let mut tree = ShvTree::new();
+        tree.default_access = AccessLevel::Read;
+        tree.add_node(".app/connection", Box::new(ConnectionNode::new(stats.clone())));
+
+        let ls = tree.process_request(&RpcMessage::create_request(".app/connection", "ls", None))?.unwrap();
+        assert_eq!(ls.as_list().len(), ConnectionStats::KEYS.len());
+        let frames_sent = tree.process_request(&RpcMessage::create_request(".app/connection/framesSent", "get", None))?.unwrap();
+        assert_eq!(frames_sent.as_u64(), 5);
+        let all = tree.process_request(&RpcMessage::create_request(".app/connection", "get", None))?.unwrap();
+        assert_eq!(all.as_map().get("framesSent").unwrap().as_u64(), 5);
+        assert!(tree.process_request(&RpcMessage::create_request(".app/connection/foo", "get", None)).is_err());
+        Ok(())
+    }
+}
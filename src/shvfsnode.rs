@@ -1,4 +1,5 @@
-use crate::shvtree::{ShvNode, ProcessRequestResult, ShvNodeHelper};
+use crate::shvtree::{AccessLevel, ShvNode, ProcessRequestResult, ShvNodeHelper};
+use crate::Error;
 use chainpack::metamethod::{MetaMethod, Signature};
 use chainpack::{RpcValue, metamethod, RpcMessage, RpcMessageMetaTags};
 use std::path::{Path, PathBuf};
@@ -41,8 +42,15 @@ impl FSDirNode {
 }
 
 impl ShvNode for FSDirNode {
+    fn required_access(&self, _shv_path: &str, method: &str) -> AccessLevel {
+        // files are read only
+        match method {
+            "dir" | "ls" => AccessLevel::Browse,
+            _ => AccessLevel::Read,
+        }
+    }
     fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> ProcessRequestResult {
-        let method = request.method().ok_or("Empty method")?;
+        let method = request.method().ok_or_else(|| Error::InvalidRequest("Empty method".into()))?;
         const M_DIR: &str = "dir";
         const M_LS: &str = "ls";
         const M_READ: &str = "read";
@@ -89,7 +97,7 @@ impl ShvNode for FSDirNode {
                 return Ok(Some(res));
             }
             else {
-                return Err("Not dir".into());
+                return Err(Error::MethodNotFound("Not dir".into()));
             }
         }
         if method == M_READ {
@@ -130,7 +138,7 @@ impl ShvNode for FSDirNode {
             }
              */
         }
-        Err(format!("Unknown method '{}' on path '{}'", method, shv_path).into())
+        Err(Error::MethodNotFound(format!("Unknown method '{}' on path '{}'", method, shv_path)))
     }
     /*
     fn is_dir(&self) -> bool {
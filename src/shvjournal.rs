@@ -6,6 +6,7 @@ use std::io::{BufRead, BufReader, Read, Seek, Write};
 use std::path::{Path, PathBuf};
 use std::str::{from_utf8_unchecked};
 use regex::Regex;
+use crate::Error;
 use log::log;
 use chainpack::{DateTime, RpcValue, List};
 use crate::shvlog::{DEFAULT_GET_LOG_RECORD_COUNT_LIMIT, DOMAIN_VAL_CHANGE, Entry, EntryValueFlags, GetLogSince, GetLogParams, LogHeader, LogHeaderField, MAX_GET_LOG_RECORD_COUNT_LIMIT, PathDict};
@@ -129,7 +130,7 @@ impl Journal {
             }
         }
         if !self.state.is_consistent() {
-            return Err("Journal state is not consistent, cannot append log entry".into());
+            return Err(Error::Journal("Journal state is not consistent, cannot append log entry".into()));
         }
         let mut datetime = entry.datetime;
         if let Some(dt) = self.state.recent_entry_datetime {
@@ -155,7 +156,7 @@ impl Journal {
     fn try_append(&mut self, datetime: &DateTime, entry: &Entry) -> crate::Result<()> {
         if !self.state.is_consistent() {
             logShvJournalE!("Append log: Inconsistent journal state");
-            return Err("Inconsistent journal state".into());
+            return Err(Error::Journal("Inconsistent journal state".into()));
         }
         let recent_entry_datatime = self.state.recent_entry_datetime.unwrap();
         let last_file_epoch_msec = *self.state.files.last().unwrap();
@@ -215,7 +216,7 @@ impl Journal {
     pub fn create_new_log_file(&mut self, datetime: &DateTime) -> crate::Result<()> {
         if let Some(recent_entry_datetime) = self.state.recent_entry_datetime {
             if datetime <= &recent_entry_datetime {
-                return Err("New file datetime must be greater than recent_entry_datetime".into())
+                return Err(Error::Journal("New file datetime must be greater than recent_entry_datetime".into()))
             }
         }
         if self.state.journal_dir_size.unwrap_or(0) > self.options.dir_size_limit {
@@ -259,7 +260,7 @@ impl Journal {
             self.try_append(datetime, &entry)?;
         }
         if self.state.last_file_size > self.options.file_size_limit {
-           return Err(format!("Snapshot is larger than log file size limit: {}", self.options.file_size_limit).into())
+           return Err(Error::Journal(format!("Snapshot is larger than log file size limit: {}", self.options.file_size_limit)))
         }
         Ok(())
     }
@@ -343,16 +344,16 @@ impl Journal {
                 return Ok(true);
             }
             if snapshot_ctx.snapshot.len() > log_ctx.record_count_limit {
-                return Err(format!("Snapshot is larger than record count limit: {}", log_ctx.record_count_limit).into());
+                return Err(Error::Journal(format!("Snapshot is larger than record count limit: {}", log_ctx.record_count_limit)));
             };
             let snapshot_dt = match snapshot_ctx.params.since {
                 GetLogSince::Some(dt) => { dt }
                 GetLogSince::LastEntry => {
                     snapshot_ctx.last_entry_datetime
-                        .ok_or("Internal error: Cannot have snapshot without last entry set")?
+                        .ok_or_else(|| Error::Journal("Internal error: Cannot have snapshot without last entry set".into()))?
                 }
                 GetLogSince::None => {
-                    return Err("Internal error: Cannot have snapshot without since defined".into());
+                    return Err(Error::Journal("Internal error: Cannot have snapshot without since defined".into()));
                 }
             };
             for (_, e) in &snapshot_ctx.snapshot.0 {
@@ -589,15 +590,15 @@ impl Journal {
         Ok(())
     }
     fn path_to_datetime(path: &std::path::Path) -> crate::Result<DateTime> {
-        let base_name = path.file_stem().ok_or(format!("Path '{:?}' is not valid log file path.", path))?;
-        let base_name = base_name.to_str().ok_or(format!("Cannot convert OsStr '{:?}' to &str", base_name))?;
+        let base_name = path.file_stem().ok_or_else(|| Error::Journal(format!("Path '{:?}' is not valid log file path.", path)))?;
+        let base_name = base_name.to_str().ok_or_else(|| Error::Journal(format!("Cannot convert OsStr '{:?}' to &str", base_name)))?;
         Self::file_base_name_to_datetime(base_name)
     }
     fn datetime_to_path(&self, datetime: &DateTime) -> crate::Result<PathBuf> {
         let file_name = Self::datetime_to_file_base_name(datetime)? + ".log2";
         let mut path = match &self.state.journal_dir {
             None => {
-                return Err("Cannot convert datetime to journal file, journal dir is invalid".into())
+                return Err(Error::Journal("Cannot convert datetime to journal file, journal dir is invalid".into()))
             }
             Some(path) => { path.clone() }
         };
@@ -611,13 +612,14 @@ impl Journal {
     //    Ok(path)
     //}
     fn file_base_name_to_datetime(filename: &str) -> crate::Result<DateTime> {
-        let dt= chrono::NaiveDateTime::parse_from_str(&filename[0 .. 23], "%Y-%m-%dT%H-%M-%S-%3f")?;
+        let dt= chrono::NaiveDateTime::parse_from_str(&filename[0 .. 23], "%Y-%m-%dT%H-%M-%S-%3f")
+            .map_err(|e| Error::Journal(format!("Invalid journal file name '{}': {}", filename, e)))?;
         Ok(DateTime::from_naive_datetime(&dt))
     }
     fn datetime_to_file_base_name(datetime: &DateTime) -> crate::Result<String> {
         let millis = datetime.epoch_msec();
         let dt= chrono::NaiveDateTime::from_timestamp_opt(millis / 1000, ((millis % 1000) * 1000000) as u32)
-            .ok_or(format!("Invalid epoch millis value: {}", millis))?;
+            .ok_or_else(|| Error::Journal(format!("Invalid epoch millis value: {}", millis)))?;
         return Ok(dt.format("%Y-%m-%dT%H-%M-%S-%3f").to_string());
     }
 
@@ -645,7 +647,7 @@ impl Journal {
             f.seek(std::io::SeekFrom::Start(chunk_start_pos as u64))?;
             let chunk_length = f.read(&mut buffer)? as usize;
             if chunk_length < TIMESTAMP_SIZE {
-                return Err(format!("Corrupted log file, cannot find complete timestamp from pos: {} of file size: {} in file: {:?}", chunk_start_pos, file_size, file).into())
+                return Err(Error::Journal(format!("Corrupted log file, cannot find complete timestamp from pos: {} of file size: {} in file: {:?}", chunk_start_pos, file_size, file)))
             }
             let it = RevLineIterator::new(&buffer[0 .. chunk_length]);
             for line in it {
@@ -662,7 +664,7 @@ impl Journal {
                 }
             }
             if chunk_start_pos == 0 {
-                return Err(format!("Corrupted log file, cannot find complete timestamp from pos: {} of file size: {} in file: {:?}", chunk_start_pos, file_size, file).into())
+                return Err(Error::Journal(format!("Corrupted log file, cannot find complete timestamp from pos: {} of file size: {} in file: {:?}", chunk_start_pos, file_size, file)))
             }
         }
     }
@@ -710,27 +712,27 @@ impl<B: BufRead> Iterator for JournalEntries<B> {
                     }
                     let mut fields = line.split('\t');
                     let datetime = match fields.next() {
-                        None => { return Some(Err("TimeStamp field missing".into())) }
+                        None => { return Some(Err(Error::Journal("TimeStamp field missing".into()))) }
                         Some(s) => {
                             match DateTime::from_iso_str(s) {
                                 Ok(dt) => { dt }
-                                Err(err) => { return Some(Err(err.into())) }
+                                Err(err) => { return Some(Err(Error::Journal(format!("Invalid timestamp '{}': {}", s, err)))) }
                             }
                         }
                     };
                     let _uptime = fields.next();
                     let path = match fields.next() {
-                        None => { return Some(Err("Path field missing".into())) }
+                        None => { return Some(Err(Error::Journal("Path field missing".into()))) }
                         Some(s) => {
-                            if s.is_empty() { return Some(Err(format!("Path is empty, line: {}", line).into())); }
+                            if s.is_empty() { return Some(Err(Error::Journal(format!("Path is empty, line: {}", line)))); }
                             s.to_string()
                         }
                     };
                     let value = match fields.next() {
-                        None => { return Some(Err("Value field missing".into())) }
+                        None => { return Some(Err(Error::Journal("Value field missing".into()))) }
                         Some(s) => { match RpcValue::from_cpon(s) {
                             Ok(v) => { v }
-                            Err(err) => { return Some(Err(err.into())) }
+                            Err(err) => { return Some(Err(Error::Journal(format!("Invalid value '{}': {}", s, err)))) }
                         }}
                     };
                     let short_time = fields.next().unwrap_or("");
@@ -739,7 +741,7 @@ impl<B: BufRead> Iterator for JournalEntries<B> {
                     } else {
                         match short_time.parse::<i32>() {
                             Ok(t) => { Some(t) }
-                            Err(err) => { return Some(Err(err.into())) }
+                            Err(err) => { return Some(Err(Error::Journal(format!("Invalid short time '{}': {}", short_time, err)))) }
                         }
                     };
                     let domain = fields.next().unwrap_or(DOMAIN_VAL_CHANGE);
@@ -750,7 +752,7 @@ impl<B: BufRead> Iterator for JournalEntries<B> {
                         Ok(i) => {
                             EntryValueFlags::from_bits_truncate(i as u8 )
                         }
-                        Err(err) => { return Some(Err(err.into())) }
+                        Err(err) => { return Some(Err(Error::Journal(format!("Invalid value flags '{}': {}", value_flags, err)))) }
                     };
                     let user_id = fields.next().unwrap_or("").to_string();
                     return Some(Ok(Entry {
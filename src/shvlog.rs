@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use bitflags::bitflags;
 use chainpack::{DateTime, List, Map, MetaMap, RpcValue, rpcvalue, Value};
 use chainpack::rpcvalue::IMap;
+use crate::Error;
 
 #[allow(unused_macros)]
 macro_rules! logShvLogE {
@@ -91,17 +92,17 @@ impl Entry {
     pub fn from_rpcvalue(record: &RpcValue) -> crate::Result<Self> {
         let record = record.as_list();
         let datetime = match record.get(LogRecordColumn::DateTime as usize) {
-            None => { return Err("Record does not contain DateTime column".into()) }
+            None => { return Err(Error::Journal("Record does not contain DateTime column".into())) }
             Some(rv) => { rv.as_datetime() }
         };
         // uptime is not used anymore
         let _uptime = record.get(LogRecordColumn::UpTime as usize);
         let path = match record.get(LogRecordColumn::Path as usize) {
-            None => { return Err("Record does not contain Path column".into()) }
+            None => { return Err(Error::Journal("Record does not contain Path column".into())) }
             Some(rv) => { rv.to_string() }
         };
         let value = match record.get(LogRecordColumn::Value as usize) {
-            None => { return Err("Record does not contain Value column".into()) }
+            None => { return Err(Error::Journal("Record does not contain Value column".into())) }
             Some(rv) => { rv }
         };
         let short_time = match record.get(LogRecordColumn::ShortTime as usize) {
@@ -0,0 +1,220 @@
+use std::sync::{Arc, Mutex};
+use chainpack::metamethod::{Flag, MetaMethod, Signature};
+use chainpack::{RpcMessage, RpcMessageMetaTags, RpcValue};
+use log::warn;
+use crate::client::FromRpcValue;
+use crate::Error;
+use crate::shvtree::{ProcessRequestResult, ShvNode, ShvNodeHelper, SignalSender};
+
+type Getter = Box<dyn FnMut() -> crate::Result<RpcValue> + Send>;
+type Setter = Box<dyn FnMut(&RpcValue) -> crate::Result<()> + Send>;
+
+struct PropertyState {
+    value: RpcValue,
+    signal_sender: Option<SignalSender>,
+}
+impl PropertyState {
+    /// Store value, emit `chng` if it differs from the cached one
+    fn update(&mut self, value: RpcValue) {
+        // RpcValue does not implement PartialEq
+        if value.to_cpon() == self.value.to_cpon() {
+            return;
+        }
+        self.value = value;
+        if let Some(signal_sender) = &self.signal_sender {
+            if let Err(e) = signal_sender.try_send_signal("", "chng", Some(self.value.clone())) {
+                warn!("Property '{}' chng signal lost: {}", signal_sender.signal_path(""), e);
+            }
+        }
+    }
+}
+
+/// Value of `PropertyNode` shared with device code, which can read and update it after the node is added to tree.
+#[derive(Clone)]
+pub struct PropertyHandle {
+    state: Arc<Mutex<PropertyState>>,
+}
+impl PropertyHandle {
+    pub fn value(&self) -> RpcValue {
+        self.state.lock().unwrap().value.clone()
+    }
+    /// Update cached value, `chng` is emitted if the value has changed
+    pub fn set_value(&self, value: impl Into<RpcValue>) {
+        self.state.lock().unwrap().update(value.into());
+    }
+}
+
+/// Node holding single value with `get`, `set` and `chng` methods.
+///
+/// `get` returns cached value or the one returned by getter callback, `set` calls setter callback
+/// and caches the new value. `chng` signal is emitted whenever the cached value changes,
+/// if the node has a `SignalSender`.
+pub struct PropertyNode {
+    state: Arc<Mutex<PropertyState>>,
+    read_only: bool,
+    description: String,
+    getter: Option<Getter>,
+    setter: Option<Setter>,
+}
+impl PropertyNode {
+    pub fn new(value: impl Into<RpcValue>) -> Self {
+        PropertyNode {
+            state: Arc::new(Mutex::new(PropertyState { value: value.into(), signal_sender: None })),
+            read_only: false,
+            description: "".into(),
+            getter: None,
+            setter: None,
+        }
+    }
+    /// Read only property does not have `set` method
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.into();
+        self
+    }
+    /// Emit `chng` signals, sender should be created for the property mount path
+    pub fn signal_sender(self, signal_sender: SignalSender) -> Self {
+        self.state.lock().unwrap().signal_sender = Some(signal_sender);
+        self
+    }
+    /// Getter called on every `get`, its result is cached
+    pub fn getter<T: Into<RpcValue>>(mut self, mut getter: impl FnMut() -> crate::Result<T> + Send + 'static) -> Self {
+        self.getter = Some(Box::new(move || getter().map(Into::into)));
+        self
+    }
+    /// Setter called on `set` with parameter converted to `T`, value is cached if setter succeeds
+    pub fn setter<T: FromRpcValue>(mut self, mut setter: impl FnMut(T) -> crate::Result<()> + Send + 'static) -> Self {
+        self.setter = Some(Box::new(move |value: &RpcValue| setter(T::from_rpcvalue(value)?)));
+        self
+    }
+    pub fn handle(&self) -> PropertyHandle {
+        PropertyHandle {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl ShvNode for PropertyNode {
+    fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> ProcessRequestResult {
+        let method = request.method().ok_or_else(|| Error::InvalidRequest("Empty method".into()))?;
+        const M_DIR: &str = "dir";
+        const M_LS: &str = "ls";
+        const M_GET: &str = "get";
+        const M_SET: &str = "set";
+        const M_CHNG: &str = "chng";
+        if !shv_path.is_empty() {
+            return Err(Error::MethodNotFound(format!("Invalid path '{}'", shv_path)));
+        }
+        #[allow(non_snake_case)]
+        if method == M_DIR {
+            let DIR = ShvNodeHelper::new_method_dir();
+            let LS = ShvNodeHelper::new_method_ls();
+            let GET = MetaMethod { name: M_GET.into(), signature: Signature::RetVoid, flags: Flag::IsGetter.into(), access_grant: RpcValue::from("rd"), description: self.description.clone() };
+            let SET = MetaMethod { name: M_SET.into(), signature: Signature::VoidParam, flags: Flag::IsSetter.into(), access_grant: RpcValue::from("wr"), description: self.description.clone() };
+            let CHNG = MetaMethod { name: M_CHNG.into(), signature: Signature::VoidParam, flags: Flag::IsSignal.into(), access_grant: RpcValue::from("rd"), description: "Value changed".into() };
+            let methods = if self.read_only { vec![DIR, LS, GET, CHNG] } else { vec![DIR, LS, GET, SET, CHNG] };
+            return Ok(Some(ShvNodeHelper::dir_result(methods.iter(), request.params())));
+        }
+        if method == M_LS {
+            let dirs: Vec<(String, bool)> = Vec::new();
+            return Ok(Some(ShvNodeHelper::ls_result(dirs.iter(), request.params())));
+        }
+        if method == M_GET {
+            if let Some(getter) = &mut self.getter {
+                let value = getter()?;
+                self.state.lock().unwrap().update(value);
+            }
+            return Ok(Some(self.state.lock().unwrap().value.clone()));
+        }
+        if method == M_SET && !self.read_only {
+            let value = request.params().ok_or_else(|| Error::InvalidParams("Value to set is missing".into()))?;
+            if let Some(setter) = &mut self.setter {
+                setter(value).map_err(|e| match e {
+                    Error::InvalidResult(msg) => Error::InvalidParams(msg),
+                    e => e,
+                })?;
+            }
+            self.state.lock().unwrap().update(value.clone());
+            return Ok(Some(true.into()));
+        }
+        Err(Error::MethodNotFound(format!("Unknown method '{}' on path '{}'", method, shv_path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use chainpack::{RpcMessage, RpcMessageMetaTags};
+    use crate::Error;
+    use crate::shvpropertynode::PropertyNode;
+    use crate::shvtree::{AccessLevel, ShvTree};
+
+    #[test]
+    fn tst_property_node() -> crate::Result<()> {
+        let mut tree = ShvTree::new();
+        tree.default_access = AccessLevel::Write;
+        let written = Arc::new(Mutex::new(0));
+        let node = {
+            let written = written.clone();
+            PropertyNode::new(1)
+                .signal_sender(tree.signal_sender("dev/level"))
+                .setter(move |value: i32| {
+                    *written.lock().unwrap() = value;
+                    Ok(())
+                })
+        };
+        let handle = node.handle();
+        tree.add_node("dev/level", Box::new(node));
+        tree.add_node("dev/name", Box::new(PropertyNode::new("dev1").read_only(true)));
+        tree.add_node("dev/label", Box::new(PropertyNode::new("").setter(|_: String| Ok(()))));
+
+        let dir = tree.process_request(&RpcMessage::create_request("dev/level", "dir", None))?.unwrap();
+        assert_eq!(dir.as_list().len(), 5);
+        let dir = tree.process_request(&RpcMessage::create_request("dev/name", "dir", None))?.unwrap();
+        assert_eq!(dir.as_list().len(), 4);
+
+        let value = tree.process_request(&RpcMessage::create_request("dev/level", "get", None))?.unwrap();
+        assert_eq!(value.as_int(), 1);
+        tree.process_request(&RpcMessage::create_request("dev/level", "set", Some(5.into())))?;
+        assert_eq!(*written.lock().unwrap(), 5);
+        assert_eq!(handle.value().as_int(), 5);
+        // the same value does not emit chng
+        tree.process_request(&RpcMessage::create_request("dev/level", "set", Some(5.into())))?;
+        handle.set_value(6);
+        assert!(matches!(tree.process_request(&RpcMessage::create_request("dev/label", "set", Some(5.into()))), Err(Error::InvalidParams(_))));
+        assert!(matches!(tree.process_request(&RpcMessage::create_request("dev/level", "set", Some("foo".into()))), Err(Error::InvalidParams(_))));
+        assert_eq!(handle.value().as_int(), 6);
+        assert!(matches!(tree.process_request(&RpcMessage::create_request("dev/name", "set", Some("foo".into()))), Err(Error::MethodNotFound(_))));
+
+        let chng = tree.response_receiver.try_recv().unwrap();
+        assert_eq!(chng.shv_path(), Some("dev/level"));
+        assert_eq!(chng.method(), Some("chng"));
+        assert_eq!(chng.params().unwrap().as_int(), 5);
+        assert_eq!(tree.response_receiver.try_recv().unwrap().params().unwrap().as_int(), 6);
+        assert!(tree.response_receiver.try_recv().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tst_mount_property_node() -> crate::Result<()> {
+        async_std::task::block_on(async {
+            let mut tree = ShvTree::new();
+            tree.default_access = AccessLevel::Read;
+            let handle = tree.handle();
+            let counter = Arc::new(Mutex::new(0));
+            let node = PropertyNode::new(0).read_only(true).getter(move || {
+                let mut counter = counter.lock().unwrap();
+                *counter += 1;
+                Ok(*counter)
+            });
+            async_std::task::spawn(async move { handle.mount("dev/counter", Box::new(node)).await }).await?;
+            tree.process_tree_commands();
+            let value = tree.process_request(&RpcMessage::create_request("dev/counter", "get", None))?.unwrap();
+            assert_eq!(value.as_int(), 1);
+            Ok(())
+        })
+    }
+}
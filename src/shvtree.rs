@@ -1,17 +1,175 @@
 use std::collections::{BTreeMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
 use async_std::channel::{Receiver, Sender};
+use async_std::{future, task};
 use chainpack::{RpcValue, RpcMessage, RpcMessageMetaTags, List};
-use log::{debug};
+use futures::FutureExt;
+use log::{debug, warn};
+use crate::client::Client;
+use crate::Error;
 use chainpack::metamethod::{Flag, MetaMethod, Signature};
 
 pub type ProcessRequestResult = crate::Result<Option<RpcValue>>;
 pub trait ShvNode {
     fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> ProcessRequestResult;
+    /// Access level required to call `method`, tree rejects requests with lower access grant
+    /// before `process_request` is called.
+    fn required_access(&self, _shv_path: &str, method: &str) -> AccessLevel {
+        AccessLevel::default_for_method(method)
+    }
     //fn is_dir(&self) -> bool;
     //fn set_rpc_response_sender(&mut self, sender: RpcResponseSender);
 }
+/// SHV access levels in ascending order
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessLevel {
+    Browse,
+    Read,
+    Write,
+    Command,
+    Config,
+    Service,
+    SuperService,
+    Devel,
+    Superuser,
+}
+impl AccessLevel {
+    pub fn to_str(&self) -> &str {
+        match self {
+            AccessLevel::Browse => "bws",
+            AccessLevel::Read => "rd",
+            AccessLevel::Write => "wr",
+            AccessLevel::Command => "cmd",
+            AccessLevel::Config => "cfg",
+            AccessLevel::Service => "srv",
+            AccessLevel::SuperService => "ssrv",
+            AccessLevel::Devel => "dev",
+            AccessLevel::Superuser => "su",
+        }
+    }
+    pub fn from_str(s: &str) -> Option<AccessLevel> {
+        match s {
+            "bws" => Some(AccessLevel::Browse),
+            "rd" => Some(AccessLevel::Read),
+            "wr" => Some(AccessLevel::Write),
+            "cmd" => Some(AccessLevel::Command),
+            "cfg" => Some(AccessLevel::Config),
+            "srv" => Some(AccessLevel::Service),
+            "ssrv" => Some(AccessLevel::SuperService),
+            "dev" => Some(AccessLevel::Devel),
+            "su" => Some(AccessLevel::Superuser),
+            _ => None,
+        }
+    }
+    /// Highest level in comma separated access grant like `rd,operator`, other items are ignored
+    pub fn from_grant(grant: &str) -> Option<AccessLevel> {
+        grant.split(',').filter_map(|item| AccessLevel::from_str(item.trim())).max()
+    }
+    /// `Browse` for `dir` and `ls`, `Read` for `get`, `Write` for anything else
+    pub fn default_for_method(method: &str) -> AccessLevel {
+        match method {
+            "dir" | "ls" => AccessLevel::Browse,
+            "get" => AccessLevel::Read,
+            _ => AccessLevel::Write,
+        }
+    }
+}
+
 pub type RpcResponseSender = Sender<RpcMessage>;
 pub type ShvNodeRef = Box<dyn ShvNode>;
+
+/// Emits signals of node mounted in `ShvTree`, created by `ShvTree::signal_sender`.
+///
+/// Signals are sent to the broker by `ShvTree::serve` together with the responses.
+/// When the client is not connected, they are queued till the channel is full.
+#[derive(Clone)]
+pub struct SignalSender {
+    node_path: String,
+    sender: RpcResponseSender,
+}
+impl SignalSender {
+    /// Full path of signal, `path` is relative to the node
+    pub fn signal_path(&self, path: &str) -> String {
+        match (self.node_path.is_empty(), path.is_empty()) {
+            (_, true) => self.node_path.clone(),
+            (true, false) => path.to_string(),
+            (false, false) => format!("{}/{}", self.node_path, path),
+        }
+    }
+    pub fn create_signal(&self, path: &str, method: &str, value: Option<RpcValue>) -> RpcMessage {
+        RpcMessage::create_signal(&self.signal_path(path), method, value)
+    }
+    pub async fn send_signal(&self, path: &str, method: &str, value: Option<RpcValue>) -> crate::Result<()> {
+        let signal = self.create_signal(path, method, value);
+        self.sender.send(signal).await.map_err(|_| Error::Other("Signal receiver dropped".into()))
+    }
+    /// Send signal without waiting, for use in synchronous `ShvNode::process_request`, fails if the queue is full
+    pub fn try_send_signal(&self, path: &str, method: &str, value: Option<RpcValue>) -> crate::Result<()> {
+        let signal = self.create_signal(path, method, value);
+        self.sender.try_send(signal).map_err(|e| Error::Other(format!("Cannot send signal: {}", e)))
+    }
+    /// Send value change signal
+    pub async fn send_chng(&self, path: &str, value: RpcValue) -> crate::Result<()> {
+        self.send_signal(path, "chng", Some(value)).await
+    }
+}
+
+pub const DEFAULT_METHOD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Result of `AsyncShvNode::process_request`, it must not borrow the node
+pub type RequestFuture = Pin<Box<dyn Future<Output = crate::Result<RpcValue>> + Send>>;
+
+/// Node, which handlers can `await`, it is added to tree by `ShvTree::add_async_node`.
+///
+/// Tree awaits returned future in spawned task, so slow handlers do not block other requests,
+/// and sends its result or error as response.
+pub trait AsyncShvNode: Send {
+    fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> RequestFuture;
+    /// See `ShvNode::required_access`
+    fn required_access(&self, _shv_path: &str, method: &str) -> AccessLevel {
+        AccessLevel::default_for_method(method)
+    }
+    /// Error response with `MethodCallTimeout` code is sent, if handler does not finish in time, `None` waits forever.
+    ///
+    /// Handler future is dropped on timeout, so it must be cancel-safe, for example child processes
+    /// have to be killed on drop.
+    fn method_timeout(&self, _method: &str) -> Option<Duration> {
+        Some(DEFAULT_METHOD_TIMEOUT)
+    }
+}
+
+/// Runs `AsyncShvNode` handlers and sends responses through tree response channel
+struct AsyncNodeAdapter {
+    node: Box<dyn AsyncShvNode>,
+    response_sender: RpcResponseSender,
+}
+impl ShvNode for AsyncNodeAdapter {
+    fn process_request(&mut self, request: &RpcMessage, shv_path: &str) -> ProcessRequestResult {
+        let mut response = request.prepare_response()?;
+        let timeout = self.node.method_timeout(request.method().unwrap_or_default());
+        let handler = self.node.process_request(request, shv_path);
+        let response_sender = self.response_sender.clone();
+        task::spawn(async move {
+            let result = match timeout {
+                Some(timeout) => future::timeout(timeout, handler).await.unwrap_or_else(|_| Err(Error::Timeout(timeout))),
+                None => handler.await,
+            };
+            match result {
+                Ok(result) => response.set_result(result),
+                Err(e) => response.set_error(e.to_rpc_error()),
+            };
+            if response_sender.send(response).await.is_err() {
+                debug!("Response dropped, tree does not exist anymore");
+            }
+        });
+        Ok(None)
+    }
+    fn required_access(&self, shv_path: &str, method: &str) -> AccessLevel {
+        self.node.required_access(shv_path, method)
+    }
+}
 type NodeMap = BTreeMap<String, ShvNodeRef>;
 
 pub struct ShvNodeHelper {
@@ -57,6 +215,11 @@ impl ShvNodeHelper {
             description: "ls() or ls([\"\", attributes]), calling ls() is the same as calling ls([\"\", 0])".into()
         }
     }
+    /// Access level declared by `access_grant` of method `name`, None if there is no such method
+    pub fn method_access<'a>(mut methods: impl Iterator<Item = &'a MetaMethod>, name: &str) -> Option<AccessLevel> {
+        methods.find(|method| method.name == name)
+            .and_then(|method| AccessLevel::from_grant(method.access_grant.as_str()))
+    }
     pub fn ls_result<'a>(dirs: impl Iterator<Item = &'a(String, bool)>, params: Option<&RpcValue>) -> RpcValue {
         let (name, attrs) = ShvNodeHelper::parse_params(params);
         let mut lst = List::new();
@@ -74,18 +237,130 @@ impl ShvNodeHelper {
     }
 }
 
+enum TreeCommand {
+    Mount { path: String, node: Box<dyn ShvNode + Send> },
+    Unmount { path: String },
+}
+
+/// Mounts and unmounts nodes of `ShvTree` from other tasks, created by `ShvTree::handle`.
+///
+/// Commands are applied by the tree before the next request is processed,
+/// `lsmod` signal is sent for every change.
+#[derive(Clone)]
+pub struct TreeHandle {
+    sender: Sender<TreeCommand>,
+    response_sender: RpcResponseSender,
+}
+impl TreeHandle {
+    pub async fn mount(&self, path: &str, node: Box<dyn ShvNode + Send>) -> crate::Result<()> {
+        self.send(TreeCommand::Mount { path: path.into(), node }).await
+    }
+    pub async fn mount_async(&self, path: &str, node: Box<dyn AsyncShvNode>) -> crate::Result<()> {
+        let response_sender = self.response_sender.clone();
+        self.mount(path, Box::new(AsyncNodeAdapter { node, response_sender })).await
+    }
+    pub async fn unmount(&self, path: &str) -> crate::Result<()> {
+        self.send(TreeCommand::Unmount { path: path.into() }).await
+    }
+    async fn send(&self, command: TreeCommand) -> crate::Result<()> {
+        self.sender.send(command).await.map_err(|_| Error::Other("Tree does not exist anymore".into()))
+    }
+}
+
 pub struct ShvTree {
     pub nodemap: NodeMap,
     pub response_sender: RpcResponseSender,
     pub response_receiver: Receiver<RpcMessage>,
+    /// Access level of requests without access grant, the broker always sets it
+    pub default_access: AccessLevel,
+    command_sender: Sender<TreeCommand>,
+    command_receiver: Receiver<TreeCommand>,
 }
 impl ShvTree {
     pub fn new() -> Self {
         let (response_sender, response_receiver) = async_std::channel::bounded(10);
+        let (command_sender, command_receiver) = async_std::channel::bounded(10);
         ShvTree {
             nodemap: BTreeMap::new(),
             response_sender,
             response_receiver,
+            default_access: AccessLevel::Browse,
+            command_sender,
+            command_receiver,
+        }
+    }
+    /// Handle to mount and unmount nodes at runtime, while the tree is served
+    pub fn handle(&self) -> TreeHandle {
+        TreeHandle {
+            sender: self.command_sender.clone(),
+            response_sender: self.response_sender.clone(),
+        }
+    }
+    /// Remove node mounted on `path`, no signal is sent
+    pub fn remove_node(&mut self, path: &str) -> Option<ShvNodeRef> {
+        self.nodemap.remove(path)
+    }
+    /// Add node and send `lsmod` signal
+    pub fn mount_node(&mut self, path: &str, node: ShvNodeRef) {
+        let added = self.top_missing_dir(path);
+        self.add_node(path, node);
+        if let Some(dir) = added {
+            self.send_lsmod(&dir, true);
+        }
+    }
+    /// Remove node and send `lsmod` signal, if the node existed
+    pub fn unmount_node(&mut self, path: &str) -> Option<ShvNodeRef> {
+        let node = self.remove_node(path)?;
+        if let Some(dir) = self.top_missing_dir(path) {
+            self.send_lsmod(&dir, false);
+        }
+        Some(node)
+    }
+    /// The highest directory on `path`, which does not exist
+    fn top_missing_dir(&self, path: &str) -> Option<String> {
+        let mut dir = String::new();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !dir.is_empty() {
+                dir.push('/');
+            }
+            dir.push_str(name);
+            if self.ls(&dir).is_none() {
+                return Some(dir);
+            }
+        }
+        None
+    }
+    /// `lsmod` signal is sent on parent of the changed directory, params are `{name: mounted}`
+    fn send_lsmod(&self, dir: &str, mounted: bool) {
+        let (parent, name) = match dir.rfind('/') {
+            Some(ix) => (&dir[.. ix], &dir[ix + 1 ..]),
+            None => ("", dir),
+        };
+        let mut params = chainpack::rpcvalue::Map::new();
+        params.insert(name.to_string(), RpcValue::from(mounted));
+        let signal = RpcMessage::create_signal(parent, "lsmod", Some(RpcValue::from(params)));
+        if let Err(e) = self.response_sender.try_send(signal) {
+            warn!("lsmod signal lost: {}", e);
+        }
+    }
+    /// Apply mount and unmount commands sent by `TreeHandle`
+    pub fn process_tree_commands(&mut self) {
+        while let Ok(command) = self.command_receiver.try_recv() {
+            self.process_tree_command(command);
+        }
+    }
+    fn process_tree_command(&mut self, command: TreeCommand) {
+        match command {
+            TreeCommand::Mount { path, node } => {
+                debug!("Mounting node: '{}'", path);
+                self.mount_node(&path, node);
+            }
+            TreeCommand::Unmount { path } => {
+                debug!("Unmounting node: '{}'", path);
+                if self.unmount_node(&path).is_none() {
+                    warn!("Cannot unmount '{}', no such node", path);
+                }
+            }
         }
     }
     pub fn add_node(&mut self, path: &str, node: ShvNodeRef) {
@@ -93,6 +368,18 @@ impl ShvTree {
         //node.set_rpc_response_sender(self.response_sender.clone());
         self.nodemap.insert(path.into(), node);
     }
+    /// Signal sender for node mounted on `node_path`
+    pub fn signal_sender(&self, node_path: &str) -> SignalSender {
+        SignalSender {
+            node_path: node_path.to_string(),
+            sender: self.response_sender.clone(),
+        }
+    }
+    /// Add node with async handlers, see `AsyncShvNode`
+    pub fn add_async_node(&mut self, path: &str, node: Box<dyn AsyncShvNode>) {
+        let response_sender = self.response_sender.clone();
+        self.add_node(path, Box::new(AsyncNodeAdapter { node, response_sender }));
+    }
     fn ls(&self, path: &str) -> Option<Vec<(String, bool)>> {
         let parent_dir = path.to_string();
         let mut dirs: Vec<(String, bool)> = Vec::new();
@@ -136,13 +423,23 @@ impl ShvTree {
             None
         }
     }
+    /// Dispatch request to the node mounted on the longest prefix of its path.
+    ///
+    /// `ls` on a path with nodes mounted below it is answered by the tree, so they can be discovered.
     pub fn process_request(&mut self, request: &RpcMessage) -> ProcessRequestResult  {
         if !request.is_request() {
-            return Err("Not request".into());
+            return Err(Error::InvalidRequest("Not request".into()));
         }
         debug!("request: {}", request);
+        self.process_tree_commands();
         let method = request.method().unwrap_or("");
         let shv_path = request.shv_path().unwrap_or("");
+        if method == "ls" {
+            // node mounted on the path does not know the nodes mounted below it
+            if let Some(dirs) = self.ls(shv_path).filter(|dirs| !dirs.is_empty()) {
+                return Ok(Some(ShvNodeHelper::ls_result(dirs.iter(), request.params())));
+            }
+        }
         let mut after_slash_ix = 0;
         loop {
             let node_dir_path;
@@ -157,6 +454,12 @@ impl ShvTree {
                 node_processor_path = "";
             }
             if let Some(node) = self.nodemap.get_mut(&node_dir_path.to_string()) {
+                let required_access = node.required_access(node_processor_path, method);
+                let access = request.access_grant().and_then(AccessLevel::from_grant).unwrap_or(self.default_access);
+                if access < required_access {
+                    return Err(Error::PermissionDenied(format!("Method '{}' on path '{}' requires '{}' access, granted: '{}'",
+                        method, shv_path, required_access.to_str(), access.to_str())));
+                }
                 let result = node.process_request(request, &node_processor_path);
                 return result;
             }
@@ -175,7 +478,74 @@ impl ShvTree {
                 return Ok(Some(ShvNodeHelper::dir_result(methods.iter(), request.params())));
             }
         }
-        Err(format!("Invalid request path: '{}'", request.shv_path().unwrap_or("INVALID")).into())
+        Err(Error::MethodNotFound(format!("Invalid request path: '{}'", request.shv_path().unwrap_or("INVALID"))))
+    }
+
+    /// Serve requests received by `client` until the connection is closed.
+    ///
+    /// Result of `process_request()` is sent back as response, errors are sent with code from `Error::to_rpc_error()`.
+    /// Signals emitted by `SignalSender` are forwarded to `client`, nodes mounted by `TreeHandle` are added.
+    /// Nodes returning `Ok(None)` send the response later through `response_sender`,
+    /// so slow handlers can run concurrently in spawned tasks without blocking other requests.
+    /// Returns `Ok(())` when the connection is closed, call `Client::close()` to stop serving.
+    pub async fn serve(&mut self, client: &Client) -> crate::Result<()> {
+        let response_receiver = self.response_receiver.clone();
+        let command_receiver = self.command_receiver.clone();
+        loop {
+            let response = futures::select! {
+                frame = client.receive_frame().fuse() => {
+                    let frame = match frame {
+                        Ok(frame) => frame,
+                        Err(Error::ConnectionClosed) => return Ok(()),
+                        Err(e) => return Err(e),
+                    };
+                    match frame.to_rpcmesage() {
+                        Ok(msg) if msg.is_request() => self.process_request_to_response(&msg),
+                        Ok(_) => None,
+                        Err(e) => {
+                            warn!("Invalid message received: {}.", e);
+                            None
+                        }
+                    }
+                },
+                // tree owns the sender, recv() cannot fail
+                msg = response_receiver.recv().fuse() => msg.ok(),
+                command = command_receiver.recv().fuse() => {
+                    if let Ok(command) = command {
+                        self.process_tree_command(command);
+                    }
+                    None
+                },
+            };
+            if let Some(response) = response {
+                debug!(target: "rpcmsg", "==> Sending response: {}", &response);
+                match client.send_message(&response).await {
+                    Ok(_) => {}
+                    Err(Error::ConnectionClosed) => return Ok(()),
+                    Err(e) => warn!("Send response error: {}.", e),
+                }
+            }
+        }
+    }
+    fn process_request_to_response(&mut self, request: &RpcMessage) -> Option<RpcMessage> {
+        let result = match self.process_request(request) {
+            // response will be sent by node through response_sender
+            Ok(None) => return None,
+            Ok(Some(result)) => Ok(result),
+            Err(e) => Err(e.to_rpc_error()),
+        };
+        let mut response = match request.prepare_response() {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Create response meta error: {}.", e);
+                return None;
+            }
+        };
+        match result {
+            Ok(result) => response.set_result(result),
+            Err(err) => response.set_error(err),
+        };
+        Some(response)
     }
 
     // fn find_node<'a, 'b>(&'a mut self, path: &'b str) -> crate::Result<(&'a mut TreeNode, &'b str)> {
@@ -206,9 +576,10 @@ impl ShvTree {
 
 #[cfg(test)]
 mod tests {
-    use chainpack::RpcMessage;
+    use chainpack::{RpcMessage, RpcMessageMetaTags};
     //use crate::client::ClientSender;
-    use crate::shvtree::{ProcessRequestResult, ShvNode, ShvTree};
+    use crate::Error;
+    use crate::shvtree::{AccessLevel, AsyncShvNode, ProcessRequestResult, RequestFuture, RpcResponseSender, ShvNode, ShvTree};
 
     struct TestNode {}
 
@@ -216,16 +587,208 @@ mod tests {
         fn process_request(&mut self, _request: &RpcMessage, _shv_path: &str) -> ProcessRequestResult {
             Ok(Some(().into()))
         }
+        fn required_access(&self, _shv_path: &str, method: &str) -> AccessLevel {
+            match method {
+                "runCmd" => AccessLevel::Command,
+                _ => AccessLevel::default_for_method(method),
+            }
+        }
     }
 
     #[test]
-    fn tst_ls() -> crate::Result<()> {
-        let (response_sender, response_receiver) = async_std::channel::bounded(10);
-        let mut tree = ShvTree {
-            nodemap: Default::default(),
-            response_sender,
-            response_receiver,
+    fn tst_access() -> crate::Result<()> {
+        let mut tree = ShvTree::new();
+        tree.add_node("a", Box::new(TestNode {}));
+        let request = |method: &str, access: Option<&str>| {
+            let mut rq = RpcMessage::create_request("a", method, None);
+            if let Some(access) = access {
+                rq.set_access_grant(access);
+            }
+            rq
         };
+        assert!(tree.process_request(&request("dir", None)).is_ok());
+        assert!(matches!(tree.process_request(&request("get", None)), Err(Error::PermissionDenied(_))));
+        assert!(tree.process_request(&request("get", Some("rd"))).is_ok());
+        assert!(matches!(tree.process_request(&request("runCmd", Some("rd"))), Err(Error::PermissionDenied(_))));
+        assert!(tree.process_request(&request("set", Some("operator,wr"))).is_ok());
+        assert!(matches!(tree.process_request(&request("runCmd", Some("operator,wr"))), Err(Error::PermissionDenied(_))));
+        assert!(tree.process_request(&request("runCmd", Some("cmd"))).is_ok());
+        assert!(tree.process_request(&request("runCmd", Some("su"))).is_ok());
+        assert_eq!(AccessLevel::from_grant("foo"), None);
+        assert!(AccessLevel::Browse < AccessLevel::Read && AccessLevel::Service < AccessLevel::Superuser);
+        Ok(())
+    }
+
+    #[test]
+    fn tst_ls_node_with_children() -> crate::Result<()> {
+        let mut tree = ShvTree::new();
+        tree.add_node("", Box::new(TestNode {}));
+        tree.add_node(".app/connection", Box::new(TestNode {}));
+        let ls = tree.process_request(&RpcMessage::create_request("", "ls", None))?.unwrap();
+        assert_eq!(ls.as_list().iter().map(|dir| dir.as_str()).collect::<Vec<_>>(), vec![".app"]);
+        // leaf node answers ls itself
+        assert!(!tree.process_request(&RpcMessage::create_request(".app/connection", "ls", None))?.unwrap().is_list());
+        Ok(())
+    }
+
+    #[test]
+    fn tst_mount() -> crate::Result<()> {
+        async_std::task::block_on(async {
+            let mut tree = ShvTree::new();
+            tree.add_node("a/b", Box::new(TestNode {}));
+            let handle = tree.handle();
+            let task = async_std::task::spawn(async move {
+                handle.mount("usb/dev1", Box::new(TestNode {})).await?;
+                handle.mount("a/c", Box::new(TestNode {})).await?;
+                handle.unmount("a/b").await
+            });
+            task.await?;
+            let ls = tree.process_request(&RpcMessage::create_request("", "ls", None))?.unwrap();
+            assert_eq!(ls.as_list().iter().map(|dir| dir.as_str()).collect::<Vec<_>>(), vec!["a", "usb"]);
+            assert!(tree.is_leaf("a/b").is_none());
+            assert_eq!(tree.is_leaf("a/c"), Some(true));
+
+            let lsmod = |signal: RpcMessage| {
+                let params = signal.params().unwrap().as_map();
+                let (name, mounted) = params.iter().next().unwrap();
+                (signal.shv_path().unwrap().to_string(), name.clone(), mounted.as_bool())
+            };
+            assert_eq!(lsmod(tree.response_receiver.try_recv().unwrap()), ("".into(), "usb".into(), true));
+            assert_eq!(lsmod(tree.response_receiver.try_recv().unwrap()), ("a".into(), "c".into(), true));
+            assert_eq!(lsmod(tree.response_receiver.try_recv().unwrap()), ("a".into(), "b".into(), false));
+            assert!(tree.unmount_node("a/c").is_some());
+            assert_eq!(lsmod(tree.response_receiver.try_recv().unwrap()), ("".into(), "a".into(), false));
+            Ok(())
+        })
+    }
+
+    struct AsyncNode {
+        response_sender: RpcResponseSender,
+    }
+
+    impl ShvNode for AsyncNode {
+        fn process_request(&mut self, request: &RpcMessage, _shv_path: &str) -> ProcessRequestResult {
+            match request.method() {
+                Some("get") => Ok(Some(42.into())),
+                Some("slow") => {
+                    let mut response = request.prepare_response()?;
+                    let response_sender = self.response_sender.clone();
+                    async_std::task::spawn(async move {
+                        async_std::task::sleep(std::time::Duration::from_millis(100)).await;
+                        response.set_result("done".into());
+                        response_sender.send(response).await
+                    });
+                    Ok(None)
+                }
+                _ => Err(Error::MethodNotFound("foo".into())),
+            }
+        }
+    }
+
+    struct SleepNode {}
+
+    impl AsyncShvNode for SleepNode {
+        fn process_request(&mut self, request: &RpcMessage, _shv_path: &str) -> RequestFuture {
+            let millis = request.params().map(|params| params.as_int()).unwrap_or_default() as u64;
+            Box::pin(async move {
+                async_std::task::sleep(std::time::Duration::from_millis(millis)).await;
+                Ok(millis.into())
+            })
+        }
+        fn method_timeout(&self, _method: &str) -> Option<std::time::Duration> {
+            Some(std::time::Duration::from_millis(100))
+        }
+    }
+
+    #[test]
+    fn tst_signal_sender() -> crate::Result<()> {
+        async_std::task::block_on(async {
+            let tree = ShvTree::new();
+            let signal_sender = tree.signal_sender("dev/temp");
+            signal_sender.send_chng("value", 21.into()).await?;
+            signal_sender.try_send_signal("", "alarm", None)?;
+            assert_eq!(tree.signal_sender("").signal_path("a"), "a");
+
+            let signal = tree.response_receiver.recv().await.unwrap();
+            assert!(signal.request_id().is_none());
+            assert_eq!(signal.shv_path(), Some("dev/temp/value"));
+            assert_eq!(signal.method(), Some("chng"));
+            assert_eq!(signal.params().unwrap().as_int(), 21);
+            let signal = tree.response_receiver.recv().await.unwrap();
+            assert_eq!(signal.shv_path(), Some("dev/temp"));
+            assert_eq!(signal.method(), Some("alarm"));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn tst_async_node() -> crate::Result<()> {
+        use chainpack::rpcmessage::RpcErrorCode;
+        async_std::task::block_on(async {
+            let mut tree = ShvTree::new();
+            tree.default_access = AccessLevel::Write;
+            tree.add_async_node("sleep", Box::new(SleepNode {}));
+            let rq = RpcMessage::create_request("sleep", "sleep", Some(10.into()));
+            assert!(tree.process_request(&rq)?.is_none());
+            let resp = tree.response_receiver.recv().await.unwrap();
+            assert_eq!(resp.request_id(), rq.request_id());
+            assert_eq!(resp.result().unwrap().as_int(), 10);
+
+            let rq = RpcMessage::create_request("sleep", "sleep", Some(1000.into()));
+            assert!(tree.process_request(&rq)?.is_none());
+            let resp = tree.response_receiver.recv().await.unwrap();
+            assert!(matches!(resp.error().unwrap().code, RpcErrorCode::MethodCallTimeout));
+            Ok(())
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tst_serve() -> crate::Result<()> {
+        use async_std::os::unix::net::UnixStream;
+        use async_std::task;
+        use chainpack::rpcframe::Protocol;
+        use chainpack::rpcmessage::RpcErrorCode;
+        use crate::Connection;
+
+        task::block_on(async {
+            let (stream, peer) = UnixStream::pair()?;
+            let (mut server_connection, server) = Connection::new(stream, Protocol::ChainPack);
+            let (mut client_connection, client) = Connection::new(peer, Protocol::ChainPack);
+            task::spawn(async move { server_connection.exec().await });
+            task::spawn(async move { client_connection.exec().await });
+            let mut tree = ShvTree::new();
+            tree.default_access = AccessLevel::Write;
+            let response_sender = tree.response_sender.clone();
+            tree.add_node("test", Box::new(AsyncNode { response_sender }));
+
+            let calls = async {
+                // slow call must not block the following ones
+                let slow = client.call_method("test", "slow", None);
+                let get = async {
+                    let started = std::time::Instant::now();
+                    let result = client.call_method("test", "get", None).await;
+                    assert!(started.elapsed() < std::time::Duration::from_millis(100));
+                    result
+                };
+                let (slow, get) = futures::join!(slow, get);
+                assert_eq!(slow?.as_str(), "done");
+                assert_eq!(get?.as_int(), 42);
+                match client.call_method("test", "foo", None).await {
+                    Err(Error::Rpc(err)) => assert!(matches!(err.code, RpcErrorCode::MethodNotFound)),
+                    other => panic!("RPC error expected, got: {:?}", other),
+                }
+                server.close().await
+            };
+            let (served, closed) = futures::join!(tree.serve(&server), calls);
+            served?;
+            closed
+        })
+    }
+
+    #[test]
+    fn tst_ls() -> crate::Result<()> {
+        let mut tree = ShvTree::new();
         tree.add_node("a/b/c",Box::new(TestNode {}));
         tree.add_node("a/1/c",Box::new(TestNode {}));
         tree.add_node("a/2",Box::new(TestNode {}));
@@ -0,0 +1,141 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use async_std::channel::{Receiver, Sender};
+use async_std::task;
+use crate::connection::ConnectionStats;
+
+/// Limits of traffic generated by `Client` and all its clones.
+///
+/// Responses and high priority calls (heart-beat ping) are never limited,
+/// they are sent ahead of the queued requests and signals.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientLimits {
+    /// Maximum number of RPC calls waiting for response, next call waits till some of them finishes
+    pub max_pending_calls: Option<usize>,
+    /// Maximum number of requests and signals sent per second
+    pub max_messages_per_sec: Option<u32>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub(crate) struct Throttle {
+    limits: ClientLimits,
+    // bounded channel used as semaphore, every pending call occupies one slot
+    pending_calls: Option<(Sender<()>, Receiver<()>)>,
+    bucket: Mutex<TokenBucket>,
+    stats: Arc<ConnectionStats>,
+}
+
+/// Slot of pending call, released when dropped
+pub(crate) struct CallPermit {
+    slot: Option<Receiver<()>>,
+    stats: Arc<ConnectionStats>,
+}
+impl Drop for CallPermit {
+    fn drop(&mut self) {
+        if let Some(slot) = &self.slot {
+            let _ = slot.try_recv();
+        }
+        self.stats.record_call_finished();
+    }
+}
+
+impl Throttle {
+    pub(crate) fn new(limits: ClientLimits, stats: Arc<ConnectionStats>) -> Self {
+        let pending_calls = match limits.max_pending_calls {
+            Some(max) if max > 0 => Some(async_std::channel::bounded(max)),
+            _ => None,
+        };
+        let tokens = limits.max_messages_per_sec.unwrap_or_default() as f64;
+        Throttle {
+            limits,
+            pending_calls,
+            bucket: Mutex::new(TokenBucket { tokens, updated: Instant::now() }),
+            stats,
+        }
+    }
+    pub(crate) fn limits(&self) -> &ClientLimits {
+        &self.limits
+    }
+    /// Wait till number of pending calls is below the limit
+    pub(crate) async fn acquire_call(&self) -> CallPermit {
+        let slot = match &self.pending_calls {
+            None => None,
+            Some((sender, receiver)) => {
+                if sender.try_send(()).is_err() {
+                    self.stats.record_throttled_call();
+                    // throttle owns both ends, the channel cannot be closed
+                    let _ = sender.send(()).await;
+                }
+                Some(receiver.clone())
+            }
+        };
+        self.stats.record_call_started();
+        CallPermit { slot, stats: self.stats.clone() }
+    }
+    /// Wait till message can be sent without exceeding the rate limit,
+    /// bursts up to `max_messages_per_sec` messages are allowed.
+    pub(crate) async fn acquire_message(&self) {
+        let rate = match self.limits.max_messages_per_sec {
+            Some(rate) if rate > 0 => rate as f64,
+            _ => return,
+        };
+        let mut throttled = false;
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(rate);
+                bucket.updated = now;
+                if bucket.tokens >= 1. {
+                    bucket.tokens -= 1.;
+                    return;
+                }
+                Duration::from_secs_f64((1. - bucket.tokens) / rate)
+            };
+            if !throttled {
+                throttled = true;
+                self.stats.record_rate_limited();
+            }
+            task::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+    use async_std::task;
+    use crate::connection::ConnectionStats;
+    use crate::throttle::{ClientLimits, Throttle};
+
+    #[test]
+    fn tst_throttle() {
+        task::block_on(async {
+            let stats = Arc::new(ConnectionStats::default());
+            let limits = ClientLimits { max_pending_calls: Some(2), max_messages_per_sec: Some(20) };
+            let throttle = Throttle::new(limits, stats.clone());
+
+            let permit1 = throttle.acquire_call().await;
+            let _permit2 = throttle.acquire_call().await;
+            assert_eq!(stats.pending_calls.load(Ordering::Relaxed), 2);
+            assert!(futures::future::FutureExt::now_or_never(throttle.acquire_call()).is_none());
+            assert_eq!(stats.throttled_calls.load(Ordering::Relaxed), 1);
+            drop(permit1);
+            assert!(futures::future::FutureExt::now_or_never(throttle.acquire_call()).is_some());
+
+            // burst of 20 messages passes, the next 10 take about half a second
+            let start = Instant::now();
+            for _ in 0 .. 30 {
+                throttle.acquire_message().await;
+            }
+            assert!(start.elapsed() >= Duration::from_millis(400));
+            assert!(stats.rate_limited.load(Ordering::Relaxed) > 0);
+        });
+    }
+}
@@ -0,0 +1,274 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use async_std::net::TcpStream;
+use futures_rustls::TlsConnector;
+use futures_rustls::client::TlsStream;
+use futures_rustls::rustls::{self, Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName};
+use futures_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+use log::{debug, warn};
+use crate::Error;
+
+#[derive(Clone, Debug)]
+pub struct TlsParams {
+    /// PEM file with CA certificates, system independent Mozilla roots are used if not specified
+    pub ca_file: Option<String>,
+    /// PEM file with client certificate chain
+    pub client_cert_file: Option<String>,
+    /// PEM file with client private key
+    pub client_key_file: Option<String>,
+    /// Check that certificate is issued for the host, only certificate chain is verified for IP address
+    pub verify_hostname: bool,
+}
+impl Default for TlsParams {
+    fn default() -> Self {
+        TlsParams {
+            ca_file: None,
+            client_cert_file: None,
+            client_key_file: None,
+            verify_hostname: true,
+        }
+    }
+}
+
+pub async fn connect(host: &str, port: u16, params: &TlsParams) -> crate::Result<TlsStream<TcpStream>> {
+    let is_ip_address = host.parse::<IpAddr>().is_ok();
+    let mut config = client_config(params, is_ip_address)?;
+    let server_name = match ServerName::try_from(host) {
+        Ok(server_name) => server_name,
+        // rustls 0.20 does not accept IP address as server name, the placeholder is not sent
+        // with SNI disabled and it is not checked by chain verifier either
+        Err(_) if is_ip_address => {
+            config.enable_sni = false;
+            ServerName::try_from(IP_ADDRESS_SERVER_NAME).expect("valid DNS name")
+        }
+        Err(e) => return Err(Error::Connection(format!("Invalid TLS server name '{}': {}", host, e))),
+    };
+    let stream = TcpStream::connect((host, port)).await?;
+    debug!("TLS handshake with: {}:{}", host, port);
+    let stream = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await?;
+    Ok(stream)
+}
+
+/// Placeholder server name used when connecting to IP address
+const IP_ADDRESS_SERVER_NAME: &str = "ip-address.invalid";
+
+fn client_config(params: &TlsParams, is_ip_address: bool) -> crate::Result<ClientConfig> {
+    let ca_certs = match &params.ca_file {
+        Some(ca_file) => Some(load_certs(ca_file)?),
+        None => None,
+    };
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let builder = if params.verify_hostname && !is_ip_address {
+        let mut roots = RootCertStore::empty();
+        match (&params.ca_file, &ca_certs) {
+            (Some(ca_file), Some(ca_certs)) => {
+                for cert in ca_certs {
+                    roots.add(cert).map_err(|e| Error::Connection(format!("Invalid CA certificate in '{}': {}", ca_file, e)))?;
+                }
+            }
+            _ => {
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+                }));
+            }
+        }
+        builder.with_root_certificates(roots)
+    } else {
+        if params.verify_hostname {
+            warn!("TLS certificate cannot be verified against IP address, only certificate chain is verified");
+        } else {
+            warn!("TLS server hostname verification is disabled");
+        }
+        builder.with_custom_certificate_verifier(Arc::new(ChainVerifier { ca_certs }))
+    };
+    let config = match (&params.client_cert_file, &params.client_key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            builder.with_single_cert(load_certs(cert_file)?, load_private_key(key_file)?)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(Error::Connection("Both client certificate and client key must be specified".into())),
+    };
+    Ok(config)
+}
+
+fn load_certs(file: &str) -> crate::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(file)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(Error::Connection(format!("No certificate found in '{}'", file)));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(file: &str) -> crate::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(file)?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(Error::Connection(format!("No private key found in '{}'", file)))
+}
+
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Verifies certificate chain, but accepts certificate issued for any host name.
+struct ChainVerifier {
+    // Mozilla roots are used if not specified
+    ca_certs: Option<Vec<Certificate>>,
+}
+
+impl ServerCertVerifier for ChainVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let invalid = |e: webpki::Error| rustls::Error::InvalidCertificateData(format!("invalid peer certificate: {:?}", e));
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref()).map_err(invalid)?;
+        let ca_anchors;
+        let anchors: &[webpki::TrustAnchor] = match &self.ca_certs {
+            Some(ca_certs) => {
+                ca_anchors = ca_certs.iter()
+                    .map(|cert| webpki::TrustAnchor::try_from_cert_der(&cert.0))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(invalid)?;
+                &ca_anchors
+            }
+            None => webpki_roots::TLS_SERVER_ROOTS.0,
+        };
+        let intermediates: Vec<&[u8]> = intermediates.iter().map(|cert| cert.0.as_ref()).collect();
+        let time = webpki::Time::try_from(now).map_err(|_| rustls::Error::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_server_cert(SUPPORTED_SIG_ALGS, &webpki::TlsServerTrustAnchors(anchors), &intermediates, time)
+            .map_err(invalid)?;
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+    use async_std::task;
+    use futures_rustls::TlsAcceptor;
+    use futures_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+    use crate::Error;
+    use crate::tls::{connect, TlsParams};
+
+    /// Directory for certificates of single test, removed when dropped
+    struct TestDir(PathBuf);
+    impl TestDir {
+        fn new(name: &str) -> crate::Result<TestDir> {
+            let dir = std::env::temp_dir().join(format!("shvapp-tst-tls-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir)?;
+            Ok(TestDir(dir))
+        }
+    }
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    async fn spawn_echo_server(dir: &TestDir, names: Vec<String>) -> crate::Result<(u16, String)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let cert = rcgen::generate_simple_self_signed(names).map_err(|e| Error::Other(e.to_string()))?;
+        let ca_file = dir.0.join(format!("ca-{}.pem", port)).to_string_lossy().to_string();
+        fs::write(&ca_file, cert.serialize_pem().map_err(|e| Error::Other(e.to_string()))?)?;
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![Certificate(cert.serialize_der().map_err(|e| Error::Other(e.to_string()))?)], PrivateKey(cert.serialize_private_key_der()))?;
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        task::spawn(async move {
+            if let Ok((stream, _)) = listener.accept().await {
+                if let Ok(mut stream) = acceptor.accept(stream).await {
+                    let mut buf = [0u8; 64];
+                    if let Ok(n) = stream.read(&mut buf).await {
+                        let _ = stream.write_all(&buf[..n]).await;
+                        let _ = stream.flush().await;
+                    }
+                }
+            }
+        });
+        Ok((port, ca_file))
+    }
+
+    async fn echo(host: &str, port: u16, params: &TlsParams) -> crate::Result<()> {
+        let mut stream = connect(host, port, params).await?;
+        stream.write_all(b"hello").await?;
+        stream.flush().await?;
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn tst_tls_connect() -> crate::Result<()> {
+        task::block_on(async {
+            let dir = TestDir::new("connect")?;
+            let (port, ca_file) = spawn_echo_server(&dir, vec!["localhost".into()]).await?;
+            let params = TlsParams { ca_file: Some(ca_file), ..Default::default() };
+            echo("localhost", port, &params).await
+        })
+    }
+
+    #[test]
+    fn tst_tls_connect_ip_address() -> crate::Result<()> {
+        task::block_on(async {
+            let dir = TestDir::new("ip")?;
+            let (port, ca_file) = spawn_echo_server(&dir, vec!["localhost".into()]).await?;
+            let params = TlsParams { ca_file: Some(ca_file), ..Default::default() };
+            echo("127.0.0.1", port, &params).await
+        })
+    }
+
+    #[test]
+    fn tst_tls_verify_hostname() -> crate::Result<()> {
+        task::block_on(async {
+            let dir = TestDir::new("verify")?;
+            let (port, ca_file) = spawn_echo_server(&dir, vec!["broker.example.com".into()]).await?;
+            let params = TlsParams { ca_file: Some(ca_file), ..Default::default() };
+            assert!(connect("localhost", port, &params).await.is_err());
+
+            let (port, ca_file) = spawn_echo_server(&dir, vec!["broker.example.com".into()]).await?;
+            let params = TlsParams { ca_file: Some(ca_file.clone()), verify_hostname: false, ..Default::default() };
+            echo("localhost", port, &params).await?;
+
+            // certificate chain is verified even without hostname verification
+            let (port, _) = spawn_echo_server(&dir, vec!["localhost".into()]).await?;
+            let params = TlsParams { ca_file: Some(ca_file), verify_hostname: false, ..Default::default() };
+            assert!(connect("localhost", port, &params).await.is_err());
+            Ok(())
+        })
+    }
+}
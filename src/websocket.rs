@@ -0,0 +1,151 @@
+//! WebSocket transport
+//!
+//! Every RPC frame is sent as one binary WebSocket message, the message content is the same
+//! as the frame sent over TCP. `WsStream` makes the WebSocket look like a byte stream, data written
+//! between two flushes is sent as single message, so one frame is written per flush.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use async_tungstenite::WebSocketStream;
+use async_tungstenite::tungstenite::Message;
+use futures::{AsyncRead, AsyncWrite, Sink, Stream};
+use log::{debug, trace};
+
+pub struct WsStream<S> {
+    ws: WebSocketStream<S>,
+    read_buffer: Vec<u8>,
+    read_pos: usize,
+    write_buffer: Vec<u8>,
+}
+
+pub async fn connect<S>(url: &str, stream: S) -> crate::Result<WsStream<S>>
+    where S: AsyncRead + AsyncWrite + Unpin
+{
+    debug!("WebSocket handshake with: {}", url);
+    let (ws, response) = async_tungstenite::client_async(url, stream).await?;
+    debug!("WebSocket handshake response status: {}", response.status());
+    Ok(WsStream::new(ws))
+}
+
+impl<S> WsStream<S> {
+    pub fn new(ws: WebSocketStream<S>) -> Self {
+        WsStream {
+            ws,
+            read_buffer: Vec::new(),
+            read_pos: 0,
+            write_buffer: Vec::new(),
+        }
+    }
+}
+
+fn to_io_error(e: async_tungstenite::tungstenite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            if self.read_pos < self.read_buffer.len() {
+                let n = buf.len().min(self.read_buffer.len() - self.read_pos);
+                let start = self.read_pos;
+                buf[.. n].copy_from_slice(&self.read_buffer[start .. start + n]);
+                self.read_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+            match Pin::new(&mut self.ws).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Err(to_io_error(e))),
+                Poll::Ready(Some(Ok(Message::Binary(data)))) => {
+                    self.read_buffer = data;
+                    self.read_pos = 0;
+                }
+                Poll::Ready(Some(Ok(Message::Close(_)))) => return Poll::Ready(Ok(0)),
+                Poll::Ready(Some(Ok(msg))) => {
+                    // ping is answered by tungstenite, text messages are not part of the protocol
+                    trace!("ignoring WebSocket message: {:?}", msg);
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.write_buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.write_buffer.is_empty() {
+            match Pin::new(&mut self.ws).poll_ready(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+                Poll::Ready(Ok(())) => {}
+            }
+            let data = std::mem::take(&mut self.write_buffer);
+            if let Err(e) = Pin::new(&mut self.ws).start_send(Message::Binary(data)) {
+                return Poll::Ready(Err(to_io_error(e)));
+            }
+        }
+        Pin::new(&mut self.ws).poll_flush(cx).map_err(to_io_error)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.as_mut().poll_flush(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        Pin::new(&mut self.ws).poll_close(cx).map_err(to_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::net::TcpListener;
+    use async_std::task;
+    use async_tungstenite::tungstenite::Message;
+    use chainpack::{RpcMessage, RpcMessageMetaTags};
+    use futures::{SinkExt, StreamExt};
+    use crate::RpcFrame;
+    use crate::client::{Client, ConnectionParams, Scheme};
+
+    /// Echo every binary message back, check that message contains exactly one frame.
+    async fn spawn_echo_server() -> crate::Result<u16> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        task::spawn(async move {
+            if let Ok((stream, _)) = listener.accept().await {
+                let mut ws = async_tungstenite::accept_async(stream).await.expect("WebSocket handshake");
+                while let Some(Ok(msg)) = ws.next().await {
+                    if let Message::Binary(data) = msg {
+                        let (len, _) = RpcFrame::parse(&data).expect("valid frame").expect("complete frame");
+                        assert_eq!(len, data.len());
+                        if ws.send(Message::Binary(data)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(port)
+    }
+
+    #[test]
+    fn tst_websocket_connect() -> crate::Result<()> {
+        task::block_on(async {
+            let port = spawn_echo_server().await?;
+            let mut params = ConnectionParams::new("127.0.0.1", port, "test", "test");
+            params.scheme = Scheme::Ws;
+            let (mut connection, client) = Client::connect(&params).await?;
+            task::spawn(async move { connection.exec().await });
+            for i in 0 .. 3 {
+                let rq = RpcMessage::create_request("test/path", "get", Some(i.into()));
+                client.send_message(&rq).await?;
+                let msg = client.receive_message().await?;
+                assert_eq!(msg.method(), Some("get"));
+                assert_eq!(msg.request_id(), rq.request_id());
+            }
+            Ok(())
+        })
+    }
+}